@@ -0,0 +1,536 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::prelude::*;
+use json::JsonValue;
+use uuid::Uuid;
+
+use crate::components;
+use crate::requests;
+use crate::reservations;
+use crate::storage;
+use crate::transactions;
+
+// Types of idToken which can be presented by a driver.
+pub const ID_TOKEN_TYPES: [&str; 8] = ["Central", "eMAID", "ISO14443", "ISO15693", "KeyCode", "Local", "MacAddress", "NoAuthorization"];
+
+// Capacity of the authorization cache, in bytes.
+pub const CACHE_STORAGE: usize = 65536;
+
+// Maximum number of entries in the local authorization list.
+pub const LOCAL_LIST_ENTRIES: usize = 1000;
+
+// IdTokenInfo of an idToken stored in the authorization cache.
+#[derive(Clone, Debug)]
+pub struct CacheEntry {
+    pub id_token: JsonValue,
+    pub id_token_info: JsonValue,
+    // When the entry was stored and used last time, in milliseconds.
+    pub created_at: u64,
+    pub last_used_at: u64,
+    // Number of times the entry authorized a driver.
+    pub use_count: u64,
+}
+
+impl CacheEntry {
+    pub fn to_json(&self) -> JsonValue {
+        object!{
+            "idToken" => self.id_token.clone(),
+            "idTokenInfo" => self.id_token_info.clone(),
+            "createdAt" => self.created_at,
+            "lastUsedAt" => self.last_used_at,
+            "useCount" => self.use_count,
+        }
+    }
+
+    pub fn from_json(data: &JsonValue) -> Option<CacheEntry> {
+        if !data["idToken"].is_object() || !data["idTokenInfo"].is_object() {
+            return None;
+        }
+
+        Some(CacheEntry {
+            id_token: data["idToken"].clone(),
+            id_token_info: data["idTokenInfo"].clone(),
+            created_at: data["createdAt"].as_u64()?,
+            last_used_at: data["lastUsedAt"].as_u64()?,
+            use_count: data["useCount"].as_u64().unwrap_or(0),
+        })
+    }
+
+    /// Checks whether the entry outlived its life time (AuthCacheCtrlr.LifeTime, in seconds) since it was used
+    /// last time, or cacheExpiryDateTime set by CSMS.
+    fn is_expired(&self, now: u64, life_time: u64) -> bool {
+        let life_time = life_time * 1000;
+
+        let cache_expiry = self.id_token_info["cacheExpiryDateTime"].as_str()
+            .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+            .map(|value| value.timestamp_millis() as u64);
+
+        now >= self.last_used_at.saturating_add(life_time) || cache_expiry.is_some_and(|cache_expiry| now >= cache_expiry)
+    }
+
+    /// Size of the entry in the cache, in bytes.
+    fn size(&self) -> usize {
+        self.to_json().dump().len()
+    }
+}
+
+// EVSE index, IdToken and remote start ID of an authorization which awaits a response to Authorize.
+type PendingAuthorization = (usize, JsonValue, Option<u64>);
+
+lazy_static! {
+    // IdTokens presented at EVSEs, or sent with remote starts, which await a response to Authorize, by message ID.
+    static ref PENDING: Mutex<HashMap<String, PendingAuthorization>> = Mutex::new(HashMap::new());
+}
+
+/// Builds an IdToken object.
+pub fn id_token(value: &str, token_type: &str) -> JsonValue {
+    object!{
+        "idToken" => value,
+        "type" => token_type,
+    }
+}
+
+/// Returns the key of an idToken in the authorization cache and the local authorization list.
+pub fn token_key(id_token: &JsonValue) -> String {
+    format!("{}:{}", id_token["type"], id_token["idToken"])
+}
+
+/// Returns the current time, in milliseconds.
+fn now() -> u64 {
+    Utc::now().timestamp_millis() as u64
+}
+
+/// Checks whether two IdTokens are the same.
+pub fn is_same_token(a: &JsonValue, b: &JsonValue) -> bool {
+    a["idToken"] == b["idToken"] && a["type"] == b["type"]
+}
+
+/// Returns status of an idToken at an EVSE according to IdTokenInfo.
+///
+/// Token may be valid only at some of the EVSEs.
+fn token_status(id_token_info: &JsonValue, evse_index: usize) -> &str {
+    let status = id_token_info["status"].as_str().unwrap_or("Invalid");
+
+    if status == "Accepted" && !id_token_info["evseId"].is_empty() && !id_token_info["evseId"].contains(evse_index + 1) {
+        return "NotAtThisLocation";
+    }
+
+    status
+}
+
+fn is_cache_enabled() -> bool {
+    components::get_bool("AuthCacheCtrlr", "Enabled") && components::get_bool("AuthCacheCtrlr", "Available")
+}
+
+/// Returns number of bytes used by the authorization cache.
+pub fn cache_size() -> usize {
+    storage::get_cache_entries().iter().map(CacheEntry::size).sum()
+}
+
+/// Removes expired entries from the authorization cache, then removes entries according to
+/// AuthCacheCtrlr.Policy until the cache fits into its storage.
+fn trim_cache() {
+    let now = now();
+    let life_time = components::get_integer("AuthCacheCtrlr", "LifeTime");
+    let mut entries = storage::get_cache_entries();
+
+    for entry in entries.iter().filter(|entry| entry.is_expired(now, life_time)) {
+        storage::delete_cache_entry(&token_key(&entry.id_token));
+    }
+
+    entries.retain(|entry| !entry.is_expired(now, life_time));
+
+    let policy = components::get_value("AuthCacheCtrlr", "Policy");
+
+    // Entries which go first are removed first. Custom policy removes invalid tokens before the least recently used ones.
+    match policy.as_str() {
+        "LFU" => entries.sort_by_key(|entry| (entry.use_count, entry.last_used_at)),
+        "FIFO" => entries.sort_by_key(|entry| entry.created_at),
+        "CUSTOM" => entries.sort_by_key(|entry| (entry.id_token_info["status"] == "Accepted", entry.last_used_at)),
+        _ => entries.sort_by_key(|entry| entry.last_used_at),
+    }
+
+    let mut size: usize = entries.iter().map(CacheEntry::size).sum();
+
+    for entry in entries.iter() {
+        if size <= CACHE_STORAGE {
+            break;
+        }
+
+        size -= entry.size();
+
+        storage::delete_cache_entry(&token_key(&entry.id_token));
+    }
+}
+
+/// Stores IdTokenInfo received from CSMS in the authorization cache.
+///
+/// Tokens from the local authorization list are not cached.
+pub fn update_cache(id_token: &JsonValue, id_token_info: &JsonValue) {
+    if !is_cache_enabled() || !id_token.is_object() || !id_token_info.is_object() {
+        return;
+    }
+
+    if is_local_list_enabled() && storage::get_local_list_entry(&token_key(id_token)).is_some() {
+        return;
+    }
+
+    let key = token_key(id_token);
+    let now = now();
+
+    let entry = match storage::get_cache_entry(&key) {
+        Some(entry) => CacheEntry {
+            id_token_info: id_token_info.clone(),
+            last_used_at: now,
+            ..entry
+        },
+        None => CacheEntry {
+            id_token: id_token.clone(),
+            id_token_info: id_token_info.clone(),
+            created_at: now,
+            last_used_at: now,
+            use_count: 0,
+        },
+    };
+
+    storage::set_cache_entry(&key, entry);
+
+    trim_cache();
+}
+
+/// Returns IdTokenInfo of an idToken from the authorization cache, unless the cached entry expired.
+fn get_cached_info(id_token: &JsonValue) -> Option<JsonValue> {
+    if !is_cache_enabled() {
+        return None;
+    }
+
+    trim_cache();
+
+    storage::get_cache_entry(&token_key(id_token)).map(|entry| entry.id_token_info)
+}
+
+/// Marks a cache entry as used to authorize a driver.
+fn use_cache_entry(id_token: &JsonValue) {
+    let key = token_key(id_token);
+
+    if let Some(mut entry) = storage::get_cache_entry(&key) {
+        entry.last_used_at = now();
+        entry.use_count += 1;
+
+        storage::set_cache_entry(&key, entry);
+    }
+}
+
+/// Clears the authorization cache. Returns Rejected if the cache is disabled.
+pub fn clear_cache() -> &'static str {
+    if !is_cache_enabled() {
+        return "Rejected";
+    }
+
+    storage::clear_cache_entries();
+
+    "Accepted"
+}
+
+fn is_local_list_enabled() -> bool {
+    components::get_bool("LocalAuthListCtrlr", "Enabled") && components::get_bool("LocalAuthListCtrlr", "Available")
+}
+
+/// Returns number of entries in the local authorization list.
+pub fn local_list_size() -> usize {
+    storage::get_local_list().len()
+}
+
+/// Updates the local authorization list with AuthorizationData from SendLocalList request.
+///
+/// Full update replaces the list. Differential update adds or updates entries with IdTokenInfo and removes
+/// entries without it, and has to have a newer version than the current list.
+/// Returns SendLocalListStatus.
+pub fn update_local_list(version_number: u64, update_type: &str, authorization_data: &JsonValue) -> &'static str {
+    if !is_local_list_enabled() {
+        return "Failed";
+    }
+
+    let mut local_list = if update_type == "Full" {
+        HashMap::new()
+    } else {
+        if version_number <= storage::get_local_list_version() {
+            return "VersionMismatch";
+        }
+
+        storage::get_local_list()
+    };
+
+    for data in authorization_data.members() {
+        let key = token_key(&data["idToken"]);
+
+        if data["idTokenInfo"].is_object() {
+            local_list.insert(key, data["idTokenInfo"].clone());
+        } else {
+            local_list.remove(&key);
+        }
+    }
+
+    if local_list.len() > LOCAL_LIST_ENTRIES {
+        return "Failed";
+    }
+
+    storage::set_local_list(version_number, local_list);
+
+    "Accepted"
+}
+
+/// Returns IdTokenInfo of an idToken from the local authorization list or the authorization cache,
+/// along with the name of the source. The list takes precedence over the cache.
+fn get_local_info(id_token: &JsonValue) -> Option<(JsonValue, &'static str)> {
+    if is_local_list_enabled() {
+        if let Some(id_token_info) = storage::get_local_list_entry(&token_key(id_token)) {
+            return Some((id_token_info, "local authorization list"));
+        }
+    }
+
+    get_cached_info(id_token).map(|id_token_info| (id_token_info, "authorization cache"))
+}
+
+/// Presents an idToken at an EVSE.
+///
+/// The token which authorized the transaction of the EVSE stops it. Otherwise the token is
+/// authorized from the local authorization list or the authorization cache when AuthCtrlr.LocalPreAuthorize
+/// is set, or while offline when AuthCtrlr.LocalAuthorizeOffline is set. Tokens which are not accepted
+/// locally are sent to CSMS with Authorize request.
+pub fn present(evse_index: usize, id_token: JsonValue) -> Result<(), String> {
+    let connector_count = match storage::get_evse(evse_index) {
+        Some(evse) => evse.connectors.len(),
+        None => return Err(format!("EVSE {} doesn't exist", evse_index + 1)),
+    };
+
+    let transaction = storage::get_evse_transaction(evse_index + 1).filter(|transaction| transaction.authorized);
+
+    let same_token = transaction.as_ref()
+        .and_then(|transaction| transaction.id_token.as_ref())
+        .is_some_and(|transaction_token| is_same_token(transaction_token, &id_token));
+
+    // The driver can stop the transaction even if the EVSE became inoperative meanwhile.
+    if same_token {
+        transactions::deauthorize(evse_index, "StopAuthorized", "Local");
+
+        return Ok(());
+    }
+
+    if !(0..connector_count).any(|connector_index| storage::is_connector_operational(evse_index, connector_index)) {
+        return Err(format!("EVSE {} is inoperative", evse_index + 1));
+    }
+
+    if transaction.is_some() {
+        return Err(format!("EVSE {} is already authorized", evse_index + 1));
+    }
+
+    request_authorization(evse_index, id_token, None)
+}
+
+/// Authorizes a token locally, or sends it to CSMS with Authorize request.
+///
+/// The token of a remote start authorizes the driver with RemoteStart trigger reason.
+fn request_authorization(evse_index: usize, id_token: JsonValue, remote_start_id: Option<u64>) -> Result<(), String> {
+    let trigger_reason = if remote_start_id.is_some() { "RemoteStart" } else { "Authorized" };
+    let online = storage::is_online();
+
+    // Tokens are not checked while authorization is disabled.
+    if !components::get_bool("AuthCtrlr", "Enabled") {
+        if !reservations::is_allowed(evse_index, &id_token, &JsonValue::Null) {
+            return Err(format!("EVSE {} is reserved for another IdToken", evse_index + 1));
+        }
+
+        transactions::authorize(evse_index, id_token, remote_start_id, trigger_reason);
+
+        return Ok(());
+    }
+
+    let local_authorization = if online {
+        components::get_bool("AuthCtrlr", "LocalPreAuthorize")
+    } else {
+        components::get_bool("AuthCtrlr", "LocalAuthorizeOffline")
+    };
+
+    if local_authorization {
+        if let Some((id_token_info, source)) = get_local_info(&id_token) {
+            let status = token_status(&id_token_info, evse_index);
+
+            println!("IdToken {} at EVSE {} is {} in the {}.", id_token["idToken"], evse_index + 1, status, source);
+
+            if status == "Accepted" {
+                if !reservations::is_allowed(evse_index, &id_token, &id_token_info["groupIdToken"]) {
+                    return Err(format!("EVSE {} is reserved for another IdToken", evse_index + 1));
+                }
+
+                use_cache_entry(&id_token);
+
+                transactions::authorize(evse_index, id_token, remote_start_id, trigger_reason);
+
+                return Ok(());
+            }
+        }
+    }
+
+    if !online {
+        // Tokens which are unknown locally may start a transaction offline if AuthCtrlr.OfflineTxForUnknownIdEnabled is set.
+        if components::get_bool("AuthCtrlr", "OfflineTxForUnknownIdEnabled") && get_local_info(&id_token).is_none() {
+            println!("IdToken {} at EVSE {} is unknown, it's accepted while offline.", id_token["idToken"], evse_index + 1);
+
+            if !reservations::is_allowed(evse_index, &id_token, &JsonValue::Null) {
+                return Err(format!("EVSE {} is reserved for another IdToken", evse_index + 1));
+            }
+
+            transactions::authorize(evse_index, id_token, remote_start_id, trigger_reason);
+
+            return Ok(());
+        }
+
+        return Err(format!("IdToken {} can't be authorized while offline", id_token["idToken"]));
+    }
+
+    let msg_id: &str = &Uuid::new_v4().to_string();
+    let msg = requests::authorize(msg_id, &id_token);
+
+    PENDING.lock().unwrap().insert(msg_id.to_string(), (evse_index, id_token, remote_start_id));
+
+    storage::queue_message(msg_id, msg);
+
+    Ok(())
+}
+
+/// Handles Authorize response. The driver is authorized only if the token is accepted at the EVSE.
+pub fn handle_response(msg_id: &str, payload: &JsonValue) {
+    let (evse_index, id_token, remote_start_id) = match PENDING.lock().unwrap().remove(msg_id) {
+        Some(res) => res,
+        None => return,
+    };
+
+    let id_token_info = &payload["idTokenInfo"];
+
+    update_cache(&id_token, id_token_info);
+
+    let status = token_status(id_token_info, evse_index);
+
+    println!("IdToken {} at EVSE {} is {}.", id_token["idToken"], evse_index + 1, status);
+
+    if status != "Accepted" {
+        return;
+    }
+
+    // Another token could authorize the driver while the response was awaited.
+    if storage::get_evse_transaction(evse_index + 1).is_some_and(|transaction| transaction.authorized) {
+        println!("EVSE {} is already authorized.", evse_index + 1);
+        return;
+    }
+
+    if !reservations::is_allowed(evse_index, &id_token, &id_token_info["groupIdToken"]) {
+        println!("EVSE {} is reserved for another IdToken.", evse_index + 1);
+        return;
+    }
+
+    let trigger_reason = if remote_start_id.is_some() { "RemoteStart" } else { "Authorized" };
+
+    transactions::authorize(evse_index, id_token, remote_start_id, trigger_reason);
+}
+
+/// Forgets the token of an Authorize request which won't get a response.
+pub fn cancel_request(msg_id: &str) {
+    if let Some((evse_index, id_token, _)) = PENDING.lock().unwrap().remove(msg_id) {
+        println!("IdToken {} at EVSE {} isn't authorized, Authorize request failed.", id_token["idToken"], evse_index + 1);
+    }
+}
+
+/// Authorizes the driver of RequestStartTransaction. The token is authorized like a token presented at the EVSE
+/// if AuthCtrlr.AuthorizeRemoteStart is set, otherwise it's trusted.
+pub fn authorize_remote_start(evse_index: usize, id_token: JsonValue, remote_start_id: u64) {
+    if !components::get_bool("AuthCtrlr", "AuthorizeRemoteStart") {
+        transactions::authorize(evse_index, id_token, Some(remote_start_id), "RemoteStart");
+
+        return;
+    }
+
+    if let Err(e) = request_authorization(evse_index, id_token, Some(remote_start_id)) {
+        println!("Remote start {} isn't authorized ({}).", remote_start_id, e);
+    }
+}
+
+/// Handles IdTokenInfo from TransactionEvent response.
+///
+/// A transaction authorized by a token which CSMS doesn't accept is stopped if TxCtrlr.StopTxOnInvalidId is set.
+pub fn handle_transaction_event_response(request_payload: &JsonValue, payload: &JsonValue) {
+    let id_token = &request_payload["idToken"];
+    let id_token_info = &payload["idTokenInfo"];
+
+    if !id_token_info.is_object() {
+        return;
+    }
+
+    update_cache(id_token, id_token_info);
+
+    let transaction = match request_payload["transactionData"]["id"].as_str().and_then(storage::get_transaction) {
+        Some(res) => res,
+        None => return,
+    };
+
+    let status = token_status(id_token_info, transaction.evse_id - 1);
+
+    if status == "Accepted" || !transaction.authorized || !components::get_bool("TxCtrlr", "StopTxOnInvalidId") {
+        return;
+    }
+
+    println!("IdToken {} of transaction {} is {}.", id_token["idToken"], transaction.id, status);
+
+    transactions::deauthorize(transaction.evse_id - 1, "Deauthorized", "DeAuthorized");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_entry_expires_after_life_time() {
+        let entry = CacheEntry {
+            id_token: id_token("AAA", "ISO14443"),
+            id_token_info: object!{ "status" => "Accepted" },
+            created_at: 0,
+            last_used_at: 1_000_000,
+            use_count: 0,
+        };
+
+        assert!(!entry.is_expired(1_000_000, 60));
+        assert!(!entry.is_expired(1_059_999, 60));
+        assert!(entry.is_expired(1_060_000, 60));
+        assert!(entry.is_expired(1_000_000, 0));
+    }
+
+    #[test]
+    fn cache_entry_expires_at_cache_expiry_date_time() {
+        let expiry = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z").unwrap().timestamp_millis() as u64;
+        let entry = CacheEntry {
+            id_token: id_token("AAA", "ISO14443"),
+            id_token_info: object!{ "status" => "Accepted", "cacheExpiryDateTime" => "2024-01-01T00:00:00Z" },
+            created_at: 0,
+            last_used_at: expiry - 10_000,
+            use_count: 0,
+        };
+
+        assert!(!entry.is_expired(expiry - 1, 86400));
+        assert!(entry.is_expired(expiry, 86400));
+    }
+
+    #[test]
+    fn cache_entry_without_valid_expiry_uses_life_time() {
+        let entry = CacheEntry {
+            id_token: id_token("AAA", "ISO14443"),
+            id_token_info: object!{ "status" => "Accepted", "cacheExpiryDateTime" => "soon" },
+            created_at: 0,
+            last_used_at: 0,
+            use_count: 0,
+        };
+
+        assert!(!entry.is_expired(59_999, 60));
+        assert!(entry.is_expired(60_000, 60));
+    }
+}
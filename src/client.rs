@@ -1,6 +1,3 @@
-use std::env;
-
-use url;
 use ws::util::Token;
 use ws::{Handler, Sender, Handshake, Result, Message, Request, Error, ErrorKind, CloseCode};
 use uuid::Uuid;
@@ -9,7 +6,15 @@ use json::JsonValue;
 
 use crate::requests;
 use crate::responses;
+use crate::authorization;
 use crate::components;
+use crate::connectors;
+use crate::transactions;
+use crate::meter;
+use crate::reservations;
+use crate::smart_charging;
+use crate::profile;
+use crate::payload::{self, CallError};
 use crate::storage;
 
 /// This macro allows to break from a code block outside of a loop.
@@ -19,24 +24,362 @@ macro_rules! block {
     };
 }
 
-static mut HEARTBEAT_INTERVAL: u64 = 0;
+/// Takes the value of a payload field, or replies with a CALLERROR and breaks from the enclosing block.
+macro_rules! field {
+    ($client:expr, $msg_id:expr, $result:expr) => {
+        match $result {
+            Ok(res) => res,
+            Err(e) => {
+                $client.send_call_error($msg_id, e)?;
+                break;
+            },
+        }
+    };
+}
 
 // Timeout events.
 const HEARTBEAT: Token = Token(1);
 const QUEUE_FETCH: Token = Token(2);
+const BOOT_RETRY: Token = Token(3);
+const RESET: Token = Token(4);
 // OCPP constants.
 const CALL: u8 = 2;
 const CALLRESULT: u8 = 3;
 const CALLERROR: u8 = 4;
 // Message queue constants.
 const QUEUE_FETCH_INTERVAL: u64 = 50;
-const QUEUE_MESSAGE_EXPIRATION: u64 = 10;
+// Seconds to wait before sending BootNotification again when CSMS didn't provide an interval.
+const DEFAULT_BOOT_RETRY_INTERVAL: u64 = 30;
+// Interval between checks whether a scheduled reset can be done, in milliseconds.
+const RESET_CHECK_INTERVAL: u64 = 1000;
+// Messages CSMS can request with TriggerMessage.
+const TRIGGER_MESSAGES: [&str; 8] = [
+    "BootNotification",
+    "LogStatusNotification",
+    "FirmwareStatusNotification",
+    "Heartbeat",
+    "MeterValues",
+    "SignChargingStationCertificate",
+    "StatusNotification",
+    "TransactionEvent",
+];
+
+/// Sends BootNotification request with identity of the station.
+fn queue_boot_notification(reason: &str) {
+    let msg_id: &str = &Uuid::new_v4().to_string();
+    let msg = requests::boot_notification(msg_id, reason, &profile::get_charging_station());
+
+    storage::set_message(msg_id.to_string(), msg.to_owned());
+
+    // Messages restored after a restart must not be sent before BootNotification.
+    storage::queue_add_front(msg);
+}
+
+/// Reports what CSMS missed while the station was offline: status of every connector, if requested and the station
+/// was offline longer than OCPPCommCtrlr.OfflineThreshold, and energy of started transactions.
+fn report_offline_state(report_status: bool) {
+    let offline_time = match storage::take_offline_time() {
+        Some(res) => res,
+        None => return,
+    };
+
+    if report_status && offline_time > components::get_integer("OCPPCommCtrlr", "OfflineThreshold") {
+        for (evse_index, evse) in storage::get_evses().iter().enumerate() {
+            for (connector_index, connector) in evse.connectors.iter().enumerate() {
+                connectors::queue_status_notification(evse_index, connector_index, connector.status);
+            }
+        }
+    }
+
+    transactions::report_offline_energy();
+}
+
+/// Sends a message requested by CSMS with TriggerMessage. Returns TriggerMessageStatus.
+///
+/// StatusNotification, MeterValues and TransactionEvent are sent for the given EVSE or connector,
+/// otherwise for every EVSE or connector. Other messages are sent for the whole station.
+fn trigger_message(requested_message: &str, evse_index: Option<usize>, connector_index: Option<usize>) -> &'static str {
+    let evse_indices: Vec<usize> = (0..storage::get_evses().len())
+        .filter(|i| evse_index.is_none_or(|evse_index| evse_index == *i))
+        .collect();
+
+    match requested_message {
+        // A station which is already registered doesn't send BootNotification again.
+        "BootNotification" if storage::is_boot_accepted() => "Rejected",
+        "BootNotification" => {
+            queue_boot_notification("Triggered");
+
+            "Accepted"
+        },
+        "Heartbeat" => {
+            let msg_id: &str = &Uuid::new_v4().to_string();
+
+            storage::queue_message(msg_id, requests::heartbeat(msg_id));
+
+            "Accepted"
+        },
+        "StatusNotification" => {
+            for evse_index in evse_indices {
+                let evse = match storage::get_evse(evse_index) {
+                    Some(res) => res,
+                    None => continue,
+                };
+
+                for (i, connector) in evse.connectors.iter().enumerate() {
+                    if connector_index.is_none_or(|connector_index| connector_index == i) {
+                        let msg_id: &str = &Uuid::new_v4().to_string();
+
+                        storage::queue_message(msg_id, requests::status_notification(msg_id, evse_index + 1, i + 1, connector.status));
+                    }
+                }
+            }
+
+            "Accepted"
+        },
+        "MeterValues" => {
+            for evse_index in evse_indices {
+                let meter_value = meter::sample(evse_index, &components::get_value("AlignedDataCtrlr", "Measurands"), "Trigger");
+
+                if !meter_value.is_empty() {
+                    let msg_id: &str = &Uuid::new_v4().to_string();
+
+                    storage::queue_message(msg_id, requests::meter_values(msg_id, evse_index + 1, meter_value));
+                }
+            }
+
+            "Accepted"
+        },
+        "TransactionEvent" => {
+            let evse_indices: Vec<usize> = evse_indices.into_iter()
+                .filter(|evse_index| storage::get_evse_transaction(evse_index + 1).is_some_and(|transaction| transaction.started))
+                .collect();
+
+            // There is nothing to report without an ongoing transaction.
+            if evse_indices.is_empty() {
+                return "Rejected";
+            }
+
+            for evse_index in evse_indices {
+                let meter_value = meter::sample_transaction(evse_index, "TxUpdatedMeasurands", "Trigger");
+
+                transactions::report_meter_values(evse_index, "Trigger", meter_value);
+            }
+
+            "Accepted"
+        },
+        // Neither log upload nor firmware update is in progress.
+        "LogStatusNotification" => {
+            let msg_id: &str = &Uuid::new_v4().to_string();
+
+            storage::queue_message(msg_id, requests::log_status_notification(msg_id, "Idle"));
+
+            "Accepted"
+        },
+        "FirmwareStatusNotification" => {
+            let msg_id: &str = &Uuid::new_v4().to_string();
+
+            storage::queue_message(msg_id, requests::firmware_status_notification(msg_id, "Idle"));
+
+            "Accepted"
+        },
+        // The station has no key pair to sign a certificate request with.
+        _ => "NotImplemented",
+    }
+}
 
 // Websocket Handler struct.
 pub struct Client {
     pub out: Sender,
 }
 
+// `ws::Result` is shared with the Handler trait, so its size is out of our control.
+#[allow(clippy::result_large_err)]
+impl Client {
+    /// Logs an error of an incoming CALL and replies with a CALLERROR message.
+    fn send_call_error(&self, msg_id: &str, error: CallError) -> Result<()> {
+        println!("CALLERROR {} ({}): {}", error.code, msg_id, error.description);
+
+        self.out.send(responses::call_error(msg_id, error.code, &error.description))
+    }
+
+    /// Schedules the next Heartbeat using OCPPCommCtrlr.HeartbeatInterval, if it's set.
+    fn schedule_heartbeat(&self) -> Result<()> {
+        let heartbeat_interval = components::get_integer("OCPPCommCtrlr", "HeartbeatInterval");
+
+        if heartbeat_interval > 0 {
+            self.out.timeout(heartbeat_interval * 1000, HEARTBEAT)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends the next message from the queue, or sends the last sent message again when it's time for a retry.
+    ///
+    /// Until BootNotification is accepted, only BootNotification is sent, other messages wait in the queue.
+    ///
+    /// Only one message awaits a response at a time. The next message is sent once the response
+    /// arrives or the last sent message is given up on.
+    fn send_next_message(&mut self) -> Result<()> {
+        let current_timestamp: u64 = Utc::now().timestamp_millis() as u64;
+
+        if let Some(mut sent_msg) = storage::get_last_sent_message() {
+            match sent_msg.retry_at {
+                Some(retry_at) if retry_at <= current_timestamp => {
+                    self.out.send(storage::get_message(&sent_msg.id))?;
+
+                    sent_msg.timestamp = current_timestamp;
+                    sent_msg.attempts += 1;
+                    sent_msg.retry_at = None;
+
+                    println!("{} ({}) was sent again, attempt {}.", sent_msg.action, sent_msg.id, sent_msg.attempts);
+
+                    storage::set_last_sent_message(sent_msg);
+
+                    return Ok(());
+                },
+                Some(_) => return Ok(()),
+                None => {
+                    let message_timeout = components::get_instance_integer("OCPPCommCtrlr", "MessageTimeout", Some("Default"));
+
+                    if sent_msg.timestamp + message_timeout * 1000 > current_timestamp {
+                        return Ok(());
+                    }
+
+                    println!("{} ({}) has no response after {} s.", sent_msg.action, sent_msg.id, message_timeout);
+
+                    if self.retry_or_drop(sent_msg)? {
+                        return Ok(());
+                    }
+                },
+            }
+        }
+
+        // Until BootNotification is accepted, nothing else may be sent.
+        let msg = if storage::is_boot_accepted() {
+            storage::queue_pop()
+        } else {
+            storage::queue_remove_action("BootNotification")
+        };
+
+        if msg.is_empty() {
+            return Ok(());
+        }
+
+        let parsed_msg = match json::parse(&msg.to_owned()) {
+            Ok(result) => result,
+            Err(e) => {
+                println!("Error during parsing of queued message: {:?}", e);
+                return Ok(());
+            },
+        };
+
+        let msg_id: &str = &parsed_msg[1].to_string();
+        let msg_action: &str = &parsed_msg[2].to_string();
+
+        self.out.send(msg)?;
+
+        println!("{} ({}) was sent.", msg_action, msg_id);
+
+        storage::set_last_sent_message(storage::SentMessage {
+            id: msg_id.to_string(),
+            action: msg_action.to_string(),
+            timestamp: current_timestamp,
+            attempts: 1,
+            retry_at: None,
+        });
+
+        Ok(())
+    }
+
+    /// Schedules another attempt to send a message which failed, or gives up on it.
+    ///
+    /// TransactionEvent is sent again, until it was sent OCPPCommCtrlr.MessageAttempts times.
+    /// BootNotification is queued again after the default interval, as if it was rejected.
+    /// Other messages are dropped, CSMS can trigger them again if needed.
+    /// Returns whether the message is going to be sent again.
+    fn retry_or_drop(&self, mut sent_msg: storage::SentMessage) -> Result<bool> {
+        let message_attempts = components::get_instance_integer("OCPPCommCtrlr", "MessageAttempts", Some("TransactionEvent"));
+
+        if sent_msg.action == "TransactionEvent" && sent_msg.attempts < message_attempts {
+            let message_attempt_interval = components::get_instance_integer("OCPPCommCtrlr", "MessageAttemptInterval", Some("TransactionEvent"));
+            let retry_interval = message_attempt_interval * sent_msg.attempts;
+
+            println!("{} ({}) will be sent again in {} s.", sent_msg.action, sent_msg.id, retry_interval);
+
+            sent_msg.retry_at = Some(Utc::now().timestamp_millis() as u64 + retry_interval * 1000);
+
+            storage::set_last_sent_message(sent_msg);
+
+            return Ok(true);
+        }
+
+        storage::clear_last_sent_message();
+        storage::delete_message(&sent_msg.id);
+
+        match sent_msg.action.as_str() {
+            "TransactionEvent" => println!("Giving up on {} ({}) after {} attempt(s).", sent_msg.action, sent_msg.id, sent_msg.attempts),
+            "BootNotification" => {
+                println!("{} ({}) failed, it will be sent again in {} s.", sent_msg.action, sent_msg.id, DEFAULT_BOOT_RETRY_INTERVAL);
+
+                self.out.timeout(DEFAULT_BOOT_RETRY_INTERVAL * 1000, BOOT_RETRY)?;
+            },
+            _ => println!("{} ({}) failed, it's dropped without another attempt.", sent_msg.action, sent_msg.id),
+        }
+
+        // The driver who waits for authorization isn't authorized.
+        if sent_msg.action == "Authorize" {
+            authorization::cancel_request(&sent_msg.id);
+        }
+
+        Ok(false)
+    }
+
+    /// Resets an EVSE, or the whole station if no EVSE is given. Transactions are stopped.
+    ///
+    /// Reset of the station closes the connection and re-initializes the state. Once connected again,
+    /// the station sends BootNotification with reason RemoteReset.
+    fn reset(&mut self, evse_index: Option<usize>) -> Result<()> {
+        let evse_indices: Vec<usize> = match evse_index {
+            Some(res) => vec![res],
+            None => (0..storage::get_evses().len()).collect(),
+        };
+
+        for evse_index in evse_indices {
+            transactions::stop(evse_index, "ResetCommand", "ImmediateReset");
+        }
+
+        if let Some(evse_index) = evse_index {
+            println!("EVSE {} was reset.", evse_index + 1);
+
+            return Ok(());
+        }
+
+        println!("Charging station is resetting.");
+
+        storage::reset();
+        storage::set_boot_reason("RemoteReset");
+
+        self.out.close(CloseCode::Normal)
+    }
+
+    /// Completes the last sent message once a response to it arrives and sends the next one right away.
+    fn complete_message(&mut self, msg_id: &str) -> Result<()> {
+        match storage::get_last_sent_message() {
+            Some(sent_msg) if sent_msg.id == msg_id => {
+                storage::clear_last_sent_message();
+                storage::delete_message(msg_id);
+
+                self.send_next_message()
+            },
+            _ => {
+                println!("Response to message {} which doesn't await a response.", msg_id);
+
+                Ok(())
+            },
+        }
+    }
+}
+
 /// We implement the Handler trait for Client so that we can get more
 /// fine-grained control of the connection.
 impl Handler for Client {
@@ -52,37 +395,45 @@ impl Handler for Client {
     /// and receiving messages.
     ///
     /// Configures interval between fetches in the message queue.
-    /// Sends BootNotification message to the message queue.
+    /// Sends BootNotification message to the message queue unless it was already accepted
+    /// before reconnection, otherwise resumes sending of Heartbeat and queued messages.
     fn on_open(&mut self, _: Handshake) -> Result<()> {
-        // Start queue worker.
-        self.out.timeout(QUEUE_FETCH_INTERVAL, QUEUE_FETCH)?;
+        storage::add_connection();
+        storage::set_connected(true);
 
-        // Get model from environment.
-        let model: String = match env::var("MODEL") {
-            Ok(var) => if var == "" { "Model".to_string() } else { var },
-            _ => "Model".to_string(),
-        };
+        // A message sent over the previous connection won't get a response.
+        // TransactionEvent has to be delivered, so it's sent again first.
+        if let Some(sent_msg) = storage::get_last_sent_message() {
+            storage::clear_last_sent_message();
 
-        // Get vendor name from environment.
-        let vendor_name: String = match env::var("VENDOR_NAME") {
-            Ok(var) => if var == "" { "Vendor name".to_string() } else { var },
-            _ => "Vendor name".to_string(),
-        };
+            if sent_msg.action == "TransactionEvent" {
+                storage::queue_add_front(storage::get_message(&sent_msg.id));
+            } else {
+                storage::delete_message(&sent_msg.id);
 
-        // Get serial number from environment.
-        let serial_number: Option<String> = match env::var("SERIAL_NUMBER") {
-            Ok(data) => Some(data),
-            _ => None,
-        };
+                if sent_msg.action == "Authorize" {
+                    authorization::cancel_request(&sent_msg.id);
+                }
+            }
+        }
 
-        // Send BootNotification request.
+        // Start queue worker.
+        self.out.timeout(QUEUE_FETCH_INTERVAL, QUEUE_FETCH)?;
+
+        // Resume waiting for scheduled resets.
+        if !storage::get_scheduled_resets().is_empty() {
+            self.out.timeout(RESET_CHECK_INTERVAL, RESET)?;
+        }
+
+        if storage::is_boot_accepted() {
+            println!("Reconnected to CSMS, {} queued message(s) will be sent.", storage::queue_size());
 
-        let msg_id: &str = &Uuid::new_v4().to_string();
-        let msg = requests::boot_notification(msg_id, "PowerUp", &model, &vendor_name, serial_number);
+            report_offline_state(true);
 
-        storage::set_message(msg_id.to_string(), msg.to_owned());
+            return self.schedule_heartbeat();
+        }
 
-        storage::queue_add(msg);
+        queue_boot_notification(storage::get_boot_reason());
 
         Ok(())
     }
@@ -91,25 +442,54 @@ impl Handler for Client {
     ///
     /// Handles requests and responses from the Charging Station Management System.
     fn on_message(&mut self, msg: Message) -> Result<()> {
-        let parsed_msg = match json::parse(msg.as_text()?) {
+        // Message ID to use in a CALLERROR when the ID of the incoming message can't be read.
+        let unknown_msg_id = "-1";
+
+        let text: &str = match msg.as_text() {
             Ok(result) => result,
-            Err(e) => panic!("Error during parsing: {:?}", e),
+            Err(_) => return self.send_call_error(unknown_msg_id, CallError::new("FormationViolation", "Message is not a text frame")),
         };
 
-        let msg_type_id = match parsed_msg[0].as_u8() {
+        let parsed_msg = match json::parse(text) {
+            Ok(result) => result,
+            Err(e) => return self.send_call_error(unknown_msg_id, CallError::new("FormationViolation", &format!("Message is not valid JSON ({})", e))),
+        };
+
+        if !parsed_msg.is_array() {
+            return self.send_call_error(unknown_msg_id, CallError::new("FormationViolation", "Message is not a JSON array"));
+        }
+
+        let msg_id: &str = match parsed_msg[1].as_str() {
             Some(res) => res,
-            None => panic!("Parsed message has no value."),
+            None => return self.send_call_error(unknown_msg_id, CallError::new("FormationViolation", "Message ID is not a string")),
         };
 
-        let msg_id: &str = &parsed_msg[1].to_string();
+        let msg_type_id = match parsed_msg[0].as_u8() {
+            Some(res) => res,
+            None => return self.send_call_error(msg_id, CallError::new("FormationViolation", "Message type ID is not a number")),
+        };
 
         println!("Message ID: {}", msg_id);
 
         match msg_type_id {
+            CALL if storage::get_registration_status() == "Rejected" => {
+                println!("Ignoring CALL while BootNotification is rejected.");
+            },
             CALL => block!({
-                let action: &str = &parsed_msg[2].to_string();
+                let action: &str = match parsed_msg[2].as_str() {
+                    Some(res) => res,
+                    None => {
+                        self.send_call_error(msg_id, CallError::new("FormationViolation", "Action is not a string"))?;
+                        break;
+                    },
+                };
                 let payload: &JsonValue = &parsed_msg[3];
 
+                if !payload.is_object() {
+                    self.send_call_error(msg_id, CallError::new("FormationViolation", "Payload is not a JSON object"))?;
+                    break;
+                }
+
                 println!("CALL Action: {}", action);
                 println!("CALL Payload: {}", payload);
 
@@ -117,33 +497,36 @@ impl Handler for Client {
                     "SetVariables" => {
                         // Send SetVariables response.
 
-                        let set_variable_data_array = &payload["setVariableData"];
+                        let set_variable_data_array = field!(self, msg_id, payload::required_array(payload, "setVariableData"));
 
-                        let mut variables: JsonValue = JsonValue::new_array();
+                        if set_variable_data_array.len() as u64 > components::get_instance_integer("DeviceDataCtrlr", "ItemsPerMessage", Some("SetVariables")) {
+                            self.send_call_error(msg_id, CallError::new("OccurenceConstraintViolation", "Too many items in setVariableData"))?;
+                            break;
+                        }
 
-                        for i in 0..set_variable_data_array.len() {
-                            let set_variable_data = &set_variable_data_array[i];
-                            let component_name: &str = &set_variable_data["component"].to_string();
-                            let variable_name: &str = &set_variable_data["variable"]["name"].to_string();
+                        // Validate every item before any variable is changed.
+                        let addresses: std::result::Result<Vec<_>, CallError> = set_variable_data_array.members().map(|set_variable_data| {
+                            let address = components::VariableAddress::from_json(set_variable_data)?;
+                            let attribute_type = payload::optional_enum(set_variable_data, "attributeType", &components::ATTRIBUTE_TYPES)?.unwrap_or("Actual");
+                            let attribute_value = payload::required_str(set_variable_data, "attributeValue")?;
 
-                            let mut variable = object!{
-                                "component" => component_name,
-                                "variable" => object!{
-                                    "name" => variable_name,
-                                },
-                            };
+                            Ok((address, attribute_type, attribute_value))
+                        }).collect();
+                        let addresses = field!(self, msg_id, addresses);
 
-                            match component_name {
-                                "AuthCtrlr" => {
-                                    match variable_name {
-                                        "AuthorizeRemoteStart" => variable["attributeStatus"] = "Rejected".into(),
-                                        _ => variable["attributeStatus"] = "UnknownVariable".into(),
-                                    }
-                                },
-                                _ => variable["attributeStatus"] = "UnknownComponent".into(),
-                            };
+                        let mut variables: JsonValue = JsonValue::new_array();
 
-                            variables.push(variable).unwrap();
+                        for (i, (address, attribute_type, attribute_value)) in addresses.iter().enumerate() {
+                            let attribute_status = components::set_variable(address, attribute_type, attribute_value);
+
+                            println!("SetVariables {}.{} ({}) = {:?}: {}", address.component, address.variable, attribute_type, attribute_value, attribute_status);
+
+                            variables.push(object!{
+                                "attributeType" => *attribute_type,
+                                "attributeStatus" => attribute_status,
+                                "component" => set_variable_data_array[i]["component"].clone(),
+                                "variable" => set_variable_data_array[i]["variable"].clone(),
+                            }).unwrap();
                         }
 
                         let response_msg: String = responses::set_variables(msg_id, variables);
@@ -153,28 +536,36 @@ impl Handler for Client {
                     "GetVariables" => {
                         // Send GetVariables response.
 
-                        let get_variable_data_array = &payload["getVariableData"];
+                        let get_variable_data_array = field!(self, msg_id, payload::required_array(payload, "getVariableData"));
 
-                        let mut variables: JsonValue = JsonValue::new_array();
+                        if get_variable_data_array.len() as u64 > components::get_instance_integer("DeviceDataCtrlr", "ItemsPerMessage", Some("GetVariables")) {
+                            self.send_call_error(msg_id, CallError::new("OccurenceConstraintViolation", "Too many items in getVariableData"))?;
+                            break;
+                        }
+
+                        let addresses: std::result::Result<Vec<_>, CallError> = get_variable_data_array.members().map(|get_variable_data| {
+                            let address = components::VariableAddress::from_json(get_variable_data)?;
+                            let attribute_type = payload::optional_enum(get_variable_data, "attributeType", &components::ATTRIBUTE_TYPES)?.unwrap_or("Actual");
 
-                        for i in 0..get_variable_data_array.len() {
-                            let get_variable_data = &get_variable_data_array[i];
-                            let component_name: &str = &get_variable_data["component"].to_string();
-                            let variable_name: &str = &get_variable_data["variable"]["name"].to_string();
+                            Ok((address, attribute_type))
+                        }).collect();
+                        let addresses = field!(self, msg_id, addresses);
 
-                            let (attribute_status, attribute_value): (&str, Option<&str>) = components::get_variable(component_name, variable_name);
+                        let mut variables: JsonValue = JsonValue::new_array();
 
+                        for (i, (address, attribute_type)) in addresses.iter().enumerate() {
                             let mut variable = object!{
-                                "attributeStatus" => attribute_status,
-                                "component" => component_name,
-                                "variable" => object!{
-                                    "name" => variable_name,
-                                },
+                                "attributeType" => *attribute_type,
+                                "component" => get_variable_data_array[i]["component"].clone(),
+                                "variable" => get_variable_data_array[i]["variable"].clone(),
                             };
 
-                            match attribute_value {
-                                Some(data) => variable["attributeValue"] = data.into(),
-                                _ => (),
+                            match components::get_variable(address, attribute_type) {
+                                Ok(value) => {
+                                    variable["attributeStatus"] = "Accepted".into();
+                                    variable["attributeValue"] = value.into();
+                                },
+                                Err(attribute_status) => variable["attributeStatus"] = attribute_status.into(),
                             };
 
                             variables.push(variable).unwrap();
@@ -183,181 +574,495 @@ impl Handler for Client {
                         let response_msg: String = responses::get_variables(msg_id, variables);
 
                         self.out.send(response_msg)?;
-                    }
+                    },
                     "RequestStartTransaction" => {
-                        let remote_start_id: u64 = match payload["remoteStartId"].as_number() {
-                            Some(res) => u64::from(res),
-                            None => panic!("Parsed message has no value."),
+                        let remote_start_id: u64 = field!(self, msg_id, payload::required_u64(payload, "remoteStartId"));
+                        let requested_evse_id: Option<u64> = field!(self, msg_id, payload::optional_u64(payload, "evseId"));
+                        let id_token: &JsonValue = field!(self, msg_id, payload::required_object(payload, "idToken"));
+                        let group_id_token: JsonValue = field!(self, msg_id, payload::optional_object(payload, "groupIdToken")).cloned().unwrap_or(JsonValue::Null);
+
+                        let charging_profile: Option<smart_charging::ChargingProfile> = match field!(self, msg_id, payload::optional_object(payload, "chargingProfile")) {
+                            Some(charging_profile) => Some(field!(self, msg_id, smart_charging::ChargingProfile::from_payload(0, charging_profile))),
+                            None => None,
+                        };
+
+                        // An EV which is plugged in and waits for authorization is preferred over an available connector.
+                        // Inoperative connectors and EVSEs reserved for other drivers can't be used.
+                        let is_waiting = |evse_index: usize| storage::get_evse_transaction(evse_index + 1)
+                            .is_some_and(|transaction| transaction.ev_connected && !transaction.authorized
+                                && transaction.connector_id.is_some_and(|connector_id| storage::is_connector_operational(evse_index, connector_id - 1)))
+                            && reservations::is_allowed(evse_index, id_token, &group_id_token);
+                        let is_free = |evse_index: usize| storage::get_evse_transaction(evse_index + 1).is_none()
+                            && connectors::find_available_connector(evse_index).is_some()
+                            && reservations::is_allowed(evse_index, id_token, &group_id_token);
+
+                        let evse_count = storage::get_evses().len();
+
+                        let evse_index: Option<usize> = match requested_evse_id {
+                            Some(res) => (res as usize).checked_sub(1).filter(|evse_index| is_waiting(*evse_index) || is_free(*evse_index)),
+                            None => (0..evse_count).find(|evse_index| is_waiting(*evse_index)).or_else(|| (0..evse_count).find(|evse_index| is_free(*evse_index))),
                         };
 
-                        // Generate transaction id.
-                        let transaction_id: &str = &Uuid::new_v4().to_string();
+                        // TxProfile is checked before its transaction starts.
+                        let evse_index = evse_index.filter(|evse_index| charging_profile.as_ref()
+                            .is_none_or(|charging_profile| smart_charging::check_remote_start_profile(charging_profile, *evse_index)));
 
-                        // Check connector status.
-                        let evse_id: usize = match payload["evseId"].as_number() {
-                            Some(res) => usize::from(res),
-                            _ => panic!("Parsed EVSE ID has no value."),
+                        let evse_index = match evse_index {
+                            Some(res) => res,
+                            None => {
+                                // Send RequestStartTransaction response.
+
+                                let request_start_transaction_msg = responses::request_start_transaction(msg_id, remote_start_id, "Rejected", None);
+
+                                self.out.send(request_start_transaction_msg)?;
+
+                                break;
+                            },
                         };
 
-                        // FIXME Magic number (connector index).
-                        let connector = storage::get_connector(evse_id - 1, 0);
+                        // Transaction ID is known when the EV is already plugged in.
+                        let transaction_id: Option<String> = storage::get_evse_transaction(evse_index + 1).map(|transaction| transaction.id);
 
-                        let mut response_status = "Accepted";
+                        // Driver plugs the EV in, unless it's plugged in already. The request is rejected if it fails.
+                        let plug_in_result = if is_waiting(evse_index) {
+                            Ok(())
+                        } else {
+                            match connectors::find_available_connector(evse_index) {
+                                Some(connector_index) => transactions::plug_in(evse_index, connector_index),
+                                None => Err(format!("EVSE {} has no available connector", evse_index + 1)),
+                            }
+                        };
 
-                        if connector.status != "Available" {
-                            response_status = "Rejected";
+                        if let Err(e) = &plug_in_result {
+                            println!("EV couldn't be plugged in ({}).", e);
                         }
 
                         // Send RequestStartTransaction response.
 
-                        let request_start_transaction_msg = responses::request_start_transaction(msg_id, remote_start_id, response_status);
+                        let status = if plug_in_result.is_ok() { "Accepted" } else { "Rejected" };
+                        let request_start_transaction_msg = responses::request_start_transaction(msg_id, remote_start_id, status, transaction_id.as_deref());
 
                         self.out.send(request_start_transaction_msg)?;
 
-                        if response_status == "Rejected" {
+                        if plug_in_result.is_err() {
                             break;
                         }
 
-                        // Set EVSE status to "Occupied" and send StatusNotification with updated status.
+                        if let Some(charging_profile) = charging_profile {
+                            smart_charging::set_remote_start_profile(charging_profile, evse_index);
+                        }
 
-                        let connector_status = "Occupied";
-                        let status_notification_msg_id: &str = &Uuid::new_v4().to_string();
-                        let status_notification_msg = requests::status_notification(status_notification_msg_id, 1, 1, connector_status);
+                        authorization::authorize_remote_start(evse_index, id_token.clone(), remote_start_id);
+                    },
+                    "RequestStopTransaction" => {
+                        let transaction_id: &str = field!(self, msg_id, payload::required_str(payload, "transactionId"));
 
-                        storage::set_message(status_notification_msg_id.to_string(), status_notification_msg.to_owned());
+                        let transaction = storage::get_transaction(transaction_id).filter(|transaction| transaction.started);
 
-                        storage::queue_add(status_notification_msg);
+                        let response_status = match transaction {
+                            Some(_) => "Accepted",
+                            None => "Rejected",
+                        };
 
-                        storage::set_connector_status(0, 0, connector_status);
+                        // Send RequestStopTransaction response.
 
-                        // Send "Started" TransactionEvent request to notify CSMS about the started transaction.
+                        let request_stop_transaction_msg = responses::request_stop_transaction(msg_id, response_status);
+
+                        self.out.send(request_stop_transaction_msg)?;
+
+                        if let Some(transaction) = transaction {
+                            transactions::deauthorize(transaction.evse_id - 1, "RemoteStop", "Remote");
+                        }
+                    },
+                    "ClearCache" => {
+                        let response_status = authorization::clear_cache();
 
-                        let transaction_event_started_msg_id: &str = &Uuid::new_v4().to_string();
-                        let transaction_event_started_msg = requests::transaction_event(transaction_event_started_msg_id, transaction_id, "Started", "RemoteStart", None, Some(remote_start_id), None);
+                        // Send ClearCache response.
 
-                        storage::set_message(transaction_event_started_msg_id.to_string(), transaction_event_started_msg.to_owned());
+                        let clear_cache_msg = responses::clear_cache(msg_id, response_status);
 
-                        storage::queue_add(transaction_event_started_msg);
+                        self.out.send(clear_cache_msg)?;
+                    },
+                    "SendLocalList" => {
+                        let version_number: u64 = field!(self, msg_id, payload::required_u64(payload, "versionNumber"));
+                        let update_type: &str = field!(self, msg_id, payload::required_enum(payload, "updateType", &["Full", "Differential"]));
+                        let authorization_data_array = field!(self, msg_id, payload::optional_array(payload, "localAuthorizationList"));
 
-                        // Save transaction.
-                        storage::set_transaction(transaction_id.to_string(), payload.dump());
+                        if authorization_data_array.len() as u64 > components::get_integer("LocalAuthListCtrlr", "ItemsPerMessage") {
+                            self.send_call_error(msg_id, CallError::new("OccurenceConstraintViolation", "Too many items in localAuthorizationList"))?;
+                            break;
+                        }
 
-                        // Send "Updated" TransactionEvent request to notify CSMS about the plugged in cable.
+                        // Validate every item before the list is changed.
+                        let validation: std::result::Result<Vec<_>, CallError> = authorization_data_array.members().map(|authorization_data| {
+                            let id_token = payload::required_object(authorization_data, "idToken")?;
 
-                        let transaction_event_updated_msg_id: &str = &Uuid::new_v4().to_string();
-                        let transaction_event_updated_msg = requests::transaction_event(transaction_event_updated_msg_id, transaction_id, "Updated", "CablePluggedIn", Some("Charging"), None, None);
+                            payload::required_str(id_token, "idToken")?;
+                            payload::required_str(id_token, "type")?;
 
-                        storage::set_message(transaction_event_updated_msg_id.to_string(), transaction_event_updated_msg.to_owned());
+                            if let Some(id_token_info) = payload::optional_object(authorization_data, "idTokenInfo")? {
+                                payload::required_str(id_token_info, "status")?;
+                            }
 
-                        storage::queue_add(transaction_event_updated_msg);
+                            Ok(())
+                        }).collect();
+                        field!(self, msg_id, validation);
+
+                        let response_status = authorization::update_local_list(version_number, update_type, &authorization_data_array);
+
+                        println!("SendLocalList {} version {}: {}", update_type, version_number, response_status);
+
+                        // Send SendLocalList response.
+
+                        let send_local_list_msg = responses::send_local_list(msg_id, response_status);
+
+                        self.out.send(send_local_list_msg)?;
                     },
-                    "RequestStopTransaction" => {
-                        let transaction_id: &str = &payload["transactionId"].to_string();
-                        // Get transaction from hash map.
-                        let transaction = storage::get_transaction(transaction_id);
+                    "GetLocalListVersion" => {
+                        // Send GetLocalListVersion response.
+
+                        let get_local_list_version_msg = responses::get_local_list_version(msg_id, storage::get_local_list_version());
+
+                        self.out.send(get_local_list_version_msg)?;
+                    },
+                    "Reset" => {
+                        let reset_type: &str = field!(self, msg_id, payload::required_enum(payload, "type", &["Immediate", "OnIdle"]));
+                        let evse_id: Option<u64> = field!(self, msg_id, payload::optional_u64(payload, "evseId"));
 
-                        let response_status = match transaction.as_str() {
-                            "" => "Rejected",
-                            _ => "Accepted",
+                        let evse_index: Option<usize> = match evse_id {
+                            Some(res) if res > 0 && res as usize <= storage::get_evses().len() => Some(res as usize - 1),
+                            Some(_) => {
+                                // Send Reset response.
+
+                                self.out.send(responses::reset(msg_id, "Rejected"))?;
+
+                                break;
+                            },
+                            None => None,
                         };
 
-                        // Send RequestStopTransaction response.
+                        // Reset on idle waits until transactions end.
+                        let response_status = if reset_type == "OnIdle" && !transactions::is_idle(evse_index) { "Scheduled" } else { "Accepted" };
 
-                        let request_stop_transaction_msg = responses::request_stop_transaction(msg_id, response_status);
+                        println!("Reset {} of {}: {}", reset_type, evse_index.map_or("the station".to_string(), |evse_index| format!("EVSE {}", evse_index + 1)), response_status);
 
-                        self.out.send(request_stop_transaction_msg)?;
+                        // Send Reset response.
+
+                        let reset_msg = responses::reset(msg_id, response_status);
+
+                        self.out.send(reset_msg)?;
+
+                        if response_status == "Scheduled" {
+                            storage::schedule_reset(evse_index);
+
+                            self.out.timeout(RESET_CHECK_INTERVAL, RESET)?;
 
-                        if response_status == "Rejected" {
                             break;
                         }
 
-                        // Send "Updated" TransactionEvent request to notify CSMS about remote stop command.
+                        self.reset(evse_index)?;
+                    },
+                    "ChangeAvailability" => {
+                        let operational_status: &str = field!(self, msg_id, payload::required_enum(payload, "operationalStatus", &["Inoperative", "Operative"]));
+
+                        // OCPP 2.0 addresses an EVSE with evseId, later versions with evse object which can address a connector.
+                        let (evse_id, connector_id): (u64, Option<u64>) = match field!(self, msg_id, payload::optional_object(payload, "evse")) {
+                            Some(evse) => (field!(self, msg_id, payload::required_u64(evse, "id")), field!(self, msg_id, payload::optional_u64(evse, "connectorId"))),
+                            None => (field!(self, msg_id, payload::optional_u64(payload, "evseId")).unwrap_or(0), None),
+                        };
+
+                        // EVSE 0 is the whole station.
+                        let evse_index: Option<usize> = (evse_id as usize).checked_sub(1);
+                        let connector_index: Option<usize> = connector_id.filter(|_| evse_index.is_some()).and_then(|connector_id| (connector_id as usize).checked_sub(1));
+
+                        let exists = match evse_index {
+                            Some(evse_index) => storage::get_evse(evse_index)
+                                .is_some_and(|evse| connector_id.is_none_or(|_| connector_index.is_some_and(|connector_index| connector_index < evse.connectors.len()))),
+                            None => true,
+                        };
+
+                        let response_status = if exists {
+                            connectors::change_availability(evse_index, connector_index, operational_status == "Operative")
+                        } else {
+                            "Rejected"
+                        };
+
+                        println!("ChangeAvailability {} of EVSE {} connector {:?}: {}", operational_status, evse_id, connector_id, response_status);
+
+                        // Send ChangeAvailability response.
+
+                        let change_availability_msg = responses::change_availability(msg_id, response_status);
+
+                        self.out.send(change_availability_msg)?;
+                    },
+                    "UnlockConnector" => {
+                        let evse_id: u64 = field!(self, msg_id, payload::required_u64(payload, "evseId"));
+                        let connector_id: u64 = field!(self, msg_id, payload::required_u64(payload, "connectorId"));
+
+                        let (evse_index, connector_index) = match ((evse_id as usize).checked_sub(1), (connector_id as usize).checked_sub(1)) {
+                            (Some(evse_index), Some(connector_index)) => (evse_index, connector_index),
+                            _ => {
+                                // Send UnlockConnector response.
+
+                                self.out.send(responses::unlock_connector(msg_id, "UnknownConnector"))?;
+
+                                break;
+                            },
+                        };
+
+                        let response_status = connectors::unlock(evse_index, connector_index);
+
+                        println!("UnlockConnector EVSE {} connector {}: {}", evse_id, connector_id, response_status);
+
+                        // Send UnlockConnector response.
+
+                        let unlock_connector_msg = responses::unlock_connector(msg_id, response_status);
+
+                        self.out.send(unlock_connector_msg)?;
+
+                        // Transaction which isn't authorized ends once the cable is unlocked.
+                        let unauthorized_transaction = storage::get_evse_transaction(evse_index + 1)
+                            .is_some_and(|transaction| transaction.connector_id == Some(connector_index + 1));
+
+                        if response_status == "Unlocked" && unauthorized_transaction {
+                            transactions::stop(evse_index, "UnlockCommand", "UnlockCommand");
+                        }
+                    },
+                    "ReserveNow" => {
+                        let id: u64 = field!(self, msg_id, payload::required_u64(payload, "id"));
+                        let expiry_date_time: DateTime<Utc> = field!(self, msg_id, payload::required_date_time(payload, "expiryDateTime"));
+                        let connector_type: Option<&str> = field!(self, msg_id, payload::optional_str(payload, "connectorType"));
+                        let evse_id: Option<u64> = field!(self, msg_id, payload::optional_u64(payload, "evseId"));
+                        let id_token: &JsonValue = field!(self, msg_id, payload::required_object(payload, "idToken"));
+                        let group_id_token: Option<&JsonValue> = field!(self, msg_id, payload::optional_object(payload, "groupIdToken"));
+
+                        // EVSE 0 is not a valid EVSE.
+                        let response_status = match evse_id {
+                            Some(evse_id) if evse_id == 0 || evse_id as usize > storage::get_evses().len() => "Rejected",
+                            _ => reservations::reserve(id, evse_id.map(|evse_id| evse_id as usize - 1), connector_type, id_token.clone(), group_id_token.cloned(), expiry_date_time),
+                        };
+
+                        println!("ReserveNow {} for EVSE {:?}: {}", id, evse_id, response_status);
+
+                        // Send ReserveNow response.
+
+                        let reserve_now_msg = responses::reserve_now(msg_id, response_status);
 
-                        let transaction_event_updated_msg_id: &str = &Uuid::new_v4().to_string();
-                        let transaction_event_updated_msg = requests::transaction_event(transaction_event_updated_msg_id, transaction_id, "Updated", "RemoteStop", None, None, None);
+                        self.out.send(reserve_now_msg)?;
+                    },
+                    "CancelReservation" => {
+                        let reservation_id: u64 = field!(self, msg_id, payload::required_u64(payload, "reservationId"));
+
+                        let response_status = reservations::cancel(reservation_id);
+
+                        // Send CancelReservation response.
+
+                        let cancel_reservation_msg = responses::cancel_reservation(msg_id, response_status);
+
+                        self.out.send(cancel_reservation_msg)?;
+                    },
+                    "SetChargingProfile" => {
+                        let evse_id: u64 = field!(self, msg_id, payload::required_u64(payload, "evseId"));
+                        let charging_profile: &JsonValue = field!(self, msg_id, payload::required_object(payload, "chargingProfile"));
+
+                        let profile = field!(self, msg_id, smart_charging::ChargingProfile::from_payload(evse_id as usize, charging_profile));
+
+                        let response_status = smart_charging::set_profile(profile);
+
+                        // Send SetChargingProfile response.
+
+                        let set_charging_profile_msg = responses::set_charging_profile(msg_id, response_status);
 
-                        storage::set_message(transaction_event_updated_msg_id.to_string(), transaction_event_updated_msg.to_owned());
+                        self.out.send(set_charging_profile_msg)?;
+                    },
+                    "GetChargingProfiles" => {
+                        let request_id: u64 = field!(self, msg_id, payload::required_u64(payload, "requestId"));
+                        let evse_id: Option<u64> = field!(self, msg_id, payload::optional_u64(payload, "evseId"));
+                        let charging_profile: &JsonValue = field!(self, msg_id, payload::required_object(payload, "chargingProfile"));
+
+                        let criteria = field!(self, msg_id, smart_charging::Criteria::from_payload(evse_id, charging_profile));
+
+                        let profiles = smart_charging::find_profiles(&criteria);
+                        let response_status = if profiles.is_empty() { "NoProfiles" } else { "Accepted" };
 
-                        storage::queue_add(transaction_event_updated_msg);
+                        println!("GetChargingProfiles {}: {} profile(s)", request_id, profiles.len());
 
-                        // Send "Ended" TransactionEvent request.
+                        // Send GetChargingProfiles response.
 
-                        let transaction_event_ended_msg_id: &str = &Uuid::new_v4().to_string();
-                        let transaction_event_ended_msg = requests::transaction_event(transaction_event_ended_msg_id, transaction_id, "Ended", "RemoteStop", None, None, Some("Remote"));
+                        let get_charging_profiles_msg = responses::get_charging_profiles(msg_id, response_status);
 
-                        storage::set_message(transaction_event_ended_msg_id.to_string(), transaction_event_ended_msg.to_owned());
+                        self.out.send(get_charging_profiles_msg)?;
+
+                        smart_charging::queue_reports(request_id, profiles);
+                    },
+                    "ClearChargingProfile" => {
+                        let charging_profile_id: Option<u64> = field!(self, msg_id, payload::optional_u64(payload, "chargingProfileId"));
+                        let criteria: Option<&JsonValue> = field!(self, msg_id, payload::optional_object(payload, "chargingProfileCriteria"));
+
+                        // Profile ID takes precedence over other criteria.
+                        let criteria = match (charging_profile_id, criteria) {
+                            (Some(charging_profile_id), _) => smart_charging::Criteria {
+                                ids: vec![charging_profile_id],
+                                ..Default::default()
+                            },
+                            (None, Some(criteria)) => {
+                                let evse_id: Option<u64> = field!(self, msg_id, payload::optional_u64(criteria, "evseId"));
+
+                                field!(self, msg_id, smart_charging::Criteria::from_payload(evse_id, criteria))
+                            },
+                            (None, None) => smart_charging::Criteria::default(),
+                        };
 
-                        storage::queue_add(transaction_event_ended_msg);
+                        let response_status = smart_charging::clear_profiles(&criteria);
 
-                        // Delete transaction.
-                        storage::delete_transaction(transaction_id);
+                        // Send ClearChargingProfile response.
 
-                        // Set EVSE status to "Available" and send StatusNotification with updated status.
+                        let clear_charging_profile_msg = responses::clear_charging_profile(msg_id, response_status);
 
-                        let connector_status = "Available";
-                        let status_notification_msg_id: &str = &Uuid::new_v4().to_string();
-                        let status_notification_msg = requests::status_notification(status_notification_msg_id, 1, 1, connector_status);
+                        self.out.send(clear_charging_profile_msg)?;
+                    },
+                    "GetCompositeSchedule" => {
+                        let duration: u64 = field!(self, msg_id, payload::required_u64(payload, "duration"));
+                        let charging_rate_unit: Option<&str> = field!(self, msg_id, payload::optional_enum(payload, "chargingRateUnit", &["A", "W"]));
+                        let evse_id: u64 = field!(self, msg_id, payload::required_u64(payload, "evseId"));
+
+                        let (response_status, schedule) = match smart_charging::composite_schedule(evse_id as usize, duration, charging_rate_unit) {
+                            Ok(res) => ("Accepted", Some(res)),
+                            Err(e) => {
+                                println!("Composite schedule of EVSE {} is rejected ({}).", evse_id, e);
+
+                                ("Rejected", None)
+                            },
+                        };
 
-                        storage::set_message(status_notification_msg_id.to_string(), status_notification_msg.to_owned());
+                        // Send GetCompositeSchedule response.
 
-                        storage::queue_add(status_notification_msg);
+                        let get_composite_schedule_msg = responses::get_composite_schedule(msg_id, response_status, schedule);
 
-                        storage::set_connector_status(0, 0, connector_status);
+                        self.out.send(get_composite_schedule_msg)?;
+                    },
+                    "TriggerMessage" => {
+                        let requested_message: &str = field!(self, msg_id, payload::required_enum(payload, "requestedMessage", &TRIGGER_MESSAGES));
+
+                        let (evse_id, connector_id): (Option<u64>, Option<u64>) = match field!(self, msg_id, payload::optional_object(payload, "evse")) {
+                            Some(evse) => (Some(field!(self, msg_id, payload::required_u64(evse, "id"))), field!(self, msg_id, payload::optional_u64(evse, "connectorId"))),
+                            None => (None, None),
+                        };
+
+                        let evse_index: Option<usize> = evse_id.and_then(|evse_id| (evse_id as usize).checked_sub(1));
+                        let connector_index: Option<usize> = connector_id.and_then(|connector_id| (connector_id as usize).checked_sub(1));
+
+                        let exists = match evse_index {
+                            Some(evse_index) => storage::get_evse(evse_index)
+                                .is_some_and(|evse| connector_id.is_none_or(|_| connector_index.is_some_and(|connector_index| connector_index < evse.connectors.len()))),
+                            None => evse_id.is_none(),
+                        };
+
+                        let response_status = if exists {
+                            trigger_message(requested_message, evse_index, connector_index)
+                        } else {
+                            "Rejected"
+                        };
+
+                        println!("TriggerMessage {} for EVSE {:?} connector {:?}: {}", requested_message, evse_id, connector_id, response_status);
+
+                        // Send TriggerMessage response.
+
+                        let trigger_message_msg = responses::trigger_message(msg_id, response_status);
+
+                        self.out.send(trigger_message_msg)?;
+                    },
+                    _ => {
+                        println!("No request handler for action: {}", action);
+
+                        self.send_call_error(msg_id, CallError::new("NotImplemented", &format!("Action {} is not implemented", action)))?;
                     },
-                    _ => println!("No request handler for action: {}", action),
                 }
             }),
-            CALLRESULT => block!({
-                let payload: &JsonValue = &parsed_msg[2];
+            CALLRESULT => {
+                block!({
+                    let payload: &JsonValue = &parsed_msg[2];
 
-                let msg_from_map = storage::get_message(msg_id);
+                    let msg_from_map = storage::get_message(msg_id);
 
-                if msg_from_map == "" {
-                    break;
-                }
+                    if msg_from_map.is_empty() {
+                        break;
+                    }
 
-                let parsed_msg_from_map = match json::parse(&msg_from_map.to_owned()) {
-                    Ok(result) => result,
-                    Err(e) => panic!("Error during parsing: {:?}", e),
-                };
+                    let parsed_msg_from_map = match json::parse(&msg_from_map.to_owned()) {
+                        Ok(result) => result,
+                        Err(e) => {
+                            println!("Error during parsing of sent message ({}): {:?}", msg_id, e);
+                            break;
+                        },
+                    };
+
+                    let msg_from_map_action: &str = &parsed_msg_from_map[2].to_string();
+                    let msg_from_map_payload: &JsonValue = &parsed_msg_from_map[3];
+
+                    match msg_from_map_action {
+                        "BootNotification" => {
+                            // Check status of the response.
+                            if payload["status"] == "Pending" || payload["status"] == "Rejected" {
+                                let registration_status = if payload["status"] == "Pending" { "Pending" } else { "Rejected" };
 
-                let msg_from_map_action: &str = &parsed_msg_from_map[2].to_string();
-                // NOTE Unused.
-                // let msg_from_map_payload: &JsonValue = &parsed_msg_from_map[3];
+                                storage::set_registration_status(registration_status);
 
-                match msg_from_map_action {
-                    "BootNotification" => {
-                        // Check status of the response.
-                        if payload["status"].to_string() == "Accepted" {
-                            println!("BootNotification was accepted.");
+                                // Send BootNotification again after the interval from the response.
+                                let retry_interval = match payload["interval"].as_u64() {
+                                    Some(res) if res > 0 => res,
+                                    _ => DEFAULT_BOOT_RETRY_INTERVAL,
+                                };
+
+                                println!("BootNotification status is {}, it will be sent again in {} s.", registration_status, retry_interval);
 
-                            // Set EVSE status to "Available" and send StatusNotification with updated status.
+                                self.out.timeout(retry_interval * 1000, BOOT_RETRY)?;
+                            }
 
-                            let connector_status = "Available";
-                            let status_notification_msg_id: &str = &Uuid::new_v4().to_string();
-                            let status_notification_msg = requests::status_notification(status_notification_msg_id, 1, 1, connector_status);
+                            if payload["status"] == "Accepted" {
+                                println!("BootNotification was accepted.");
 
-                            storage::set_message(status_notification_msg_id.to_string(), status_notification_msg.to_owned());
+                                storage::set_registration_status("Accepted");
+                                storage::set_boot_reason("PowerUp");
 
-                            storage::queue_add(status_notification_msg);
+                                // Send StatusNotification with status of every connector.
+                                // Connectors occupied by transactions restored after a restart stay "Occupied".
+                                for (evse_index, evse) in storage::get_evses().iter().enumerate() {
+                                    for (connector_index, connector) in evse.connectors.iter().enumerate() {
+                                        let connector_status = if connector.status == "Occupied" {
+                                            "Occupied"
+                                        } else {
+                                            connectors::idle_status(evse_index, connector_index)
+                                        };
+
+                                        connectors::queue_status_notification(evse_index, connector_index, connector_status);
+                                    }
+                                }
 
-                            storage::set_connector_status(0, 0, connector_status);
+                                // Status of every connector is reported above.
+                                report_offline_state(false);
 
-                            // Schedule a Heartbeat using the interval from BootNotification.
+                                // Schedule a Heartbeat using the interval from BootNotification.
 
-                            unsafe {
-                                match payload["interval"].as_number() {
-                                    Some(res) => HEARTBEAT_INTERVAL = u64::from(res) * 1000,
-                                    None => panic!("Parsed message has no value."),
+                                match payload["interval"].as_u64() {
+                                    Some(res) => components::set_value("OCPPCommCtrlr", "HeartbeatInterval", &res.to_string()),
+                                    None => {
+                                        println!("BootNotification response has no valid interval.");
+                                        break;
+                                    },
                                 };
 
-                                self.out.timeout(HEARTBEAT_INTERVAL, HEARTBEAT)?;
+                                self.schedule_heartbeat()?;
                             }
-                        }
-                    },
-                    _=> println!("No response handler for action: {}", msg_from_map_action),
-                }
-            }),
+                        },
+                        "Authorize" => authorization::handle_response(msg_id, payload),
+                        "TransactionEvent" => authorization::handle_transaction_event_response(msg_from_map_payload, payload),
+                        _=> println!("No response handler for action: {}", msg_from_map_action),
+                    }
+                });
+
+                self.complete_message(msg_id)?;
+            },
             CALLERROR => {
                 let error_code: &str = &parsed_msg[2].to_string();
                 let error_description: &str = &parsed_msg[3].to_string();
@@ -366,24 +1071,36 @@ impl Handler for Client {
                 println!("CALLERROR Error code: {}", error_code);
                 println!("CALLERROR Error Description: {}", error_description);
                 println!("CALLERROR Error details: {}", error_details);
+
+                match storage::get_last_sent_message() {
+                    Some(sent_msg) if sent_msg.id == msg_id => {
+                        if !self.retry_or_drop(sent_msg)? {
+                            self.send_next_message()?;
+                        }
+                    },
+                    _ => println!("Error for message {} which doesn't await a response.", msg_id),
+                }
+            },
+            _ => {
+                println!("Unknown message type ID: {}", msg_type_id);
+
+                self.send_call_error(msg_id, CallError::new("FormationViolation", &format!("Unknown message type ID {}", msg_type_id)))?;
             },
-            _ => println!("Unknown message type ID"),
         }
 
         Ok(())
     }
 
     /// Called any time this endpoint receives a close control frame.
+    ///
+    /// The station keeps its state and reconnects once the connection is closed.
     fn on_close(&mut self, code: CloseCode, reason: &str) {
-       println!("WebSocket closing for ({:?}) {}", code, reason);
-       println!("Shutting down server after first connection closes.");
-       self.out.shutdown().unwrap();
-   }
-
-   /// Shutdown on any error.
-   fn on_error(&mut self, err: Error) {
-        println!("Shutting down server for error: {}", err);
-        self.out.shutdown().unwrap();
+        println!("WebSocket closing for ({:?}) {}", code, reason);
+    }
+
+    /// Called on any error. The connection is dropped and the station reconnects.
+    fn on_error(&mut self, err: Error) {
+        println!("WebSocket error: {:?}", err);
     }
 
     /// Called when a timeout has been scheduled on the eventloop.
@@ -392,6 +1109,11 @@ impl Handler for Client {
     /// Fetches and sends messages from the message queue.
     fn on_timeout(&mut self, event: Token) -> Result<()> {
         match event {
+            BOOT_RETRY => {
+                queue_boot_notification(storage::get_boot_reason());
+
+                Ok(())
+            },
             HEARTBEAT => {
                 // Send Heartbeat message.
 
@@ -403,43 +1125,35 @@ impl Handler for Client {
                 storage::queue_add(msg);
 
                 // Schedule next message.
-                unsafe {
-                    self.out.timeout(HEARTBEAT_INTERVAL, HEARTBEAT)?;
-                }
-
-                Ok(())
+                self.schedule_heartbeat()
             },
-            QUEUE_FETCH => {
-                let current_timestamp: u64 = Utc::now().timestamp() as u64;
-
-                let last_sent_msg = storage::get_last_sent_message();
-                // Check whether last sent message exists or not.
-                let last_sent_msg_exist: bool = last_sent_msg.id != None;
-                // Check whether last sent message has expired or not.
-                let last_sent_msg_expired: bool = match last_sent_msg.timestamp {
-                    Some(timestamp) => timestamp + QUEUE_MESSAGE_EXPIRATION < current_timestamp,
-                    None => true,
-                };
+            RESET => {
+                for evse_index in storage::get_scheduled_resets() {
+                    if !transactions::is_idle(evse_index) {
+                        continue;
+                    }
 
-                if storage::queue_size() > 0 && (!last_sent_msg_exist || last_sent_msg_expired) {
-                    let msg = storage::queue_pop();
+                    storage::delete_scheduled_reset(evse_index);
 
-                    if msg != "" {
-                        let parsed_msg = match json::parse(&msg.to_owned()) {
-                            Ok(result) => result,
-                            Err(e) => panic!("Error during parsing: {:?}", e),
-                        };
+                    self.reset(evse_index)?;
 
-                        let msg_id: &str = &parsed_msg[1].to_string();
-                        let msg_action: &str = &parsed_msg[2].to_string();
+                    // The station is reset along with all of its EVSEs.
+                    if evse_index.is_none() {
+                        return Ok(());
+                    }
+                }
 
-                        self.out.send(msg)?;
+                if !storage::get_scheduled_resets().is_empty() {
+                    self.out.timeout(RESET_CHECK_INTERVAL, RESET)?;
+                }
 
-                        println!("{} ({}) was sent.", msg_action, msg_id);
+                Ok(())
+            },
+            QUEUE_FETCH => {
+                self.send_next_message()?;
 
-                        storage::set_last_sent_message(msg_id.to_string(), current_timestamp);
-                    }
-                }
+                // Changes of the state which were not saved right away are saved in intervals.
+                storage::persist_changes();
 
                 self.out.timeout(QUEUE_FETCH_INTERVAL, QUEUE_FETCH)?;
 
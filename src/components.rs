@@ -1,11 +1,469 @@
-pub fn get_variable(component_name: &str, variable_name: &str) -> (&'static str, Option<&'static str>) {
-    match component_name {
-        "AuthCtrlr" => {
-            match variable_name {
-                "AuthorizeRemoteStart" => ("Accepted", Some("false")),
-                _ => ("UnknownVariable", None),
-            }
-        },
-        _ => ("UnknownComponent", None),
+use std::sync::Mutex;
+
+use chrono::DateTime;
+use json::JsonValue;
+
+use crate::authorization;
+use crate::load_balancing;
+use crate::smart_charging;
+use crate::payload::{self, CallError};
+use crate::storage;
+
+// Values of measurand lists in SampledDataCtrlr and AlignedDataCtrlr: measurands which meters sample.
+const MEASURANDS: &str = "Current.Import,Energy.Active.Import.Register,Power.Active.Import,Power.Offered,SoC,Voltage";
+// Values of TxStartPoint and TxStopPoint.
+const TX_POINTS: &str = "ParkingBayOccupancy,EVConnected,Authorized,DataSigned,PowerPathClosed,EnergyTransfer";
+// Values of AvailabilityState.
+const AVAILABILITY_STATES: &str = "Available,Occupied,Reserved,Unavailable,Faulted";
+
+pub const ATTRIBUTE_TYPES: [&str; 4] = ["Actual", "Target", "MinSet", "MaxSet"];
+
+// Variable attribute struct.
+#[derive(Clone, Debug)]
+pub struct Attribute {
+    pub attribute_type: &'static str,
+    pub value: String,
+    pub mutability: &'static str,
+}
+
+// Variable of a component in the device model.
+#[derive(Clone, Debug)]
+pub struct Variable {
+    pub component: &'static str,
+    pub component_instance: Option<&'static str>,
+    pub evse_id: Option<usize>,
+    pub connector_id: Option<usize>,
+    pub name: &'static str,
+    pub instance: Option<&'static str>,
+    pub attributes: Vec<Attribute>,
+    // Variable characteristics.
+    pub data_type: &'static str,
+    pub unit: Option<&'static str>,
+    pub min_limit: Option<f64>,
+    pub max_limit: Option<f64>,
+    pub values_list: Option<&'static str>,
+}
+
+impl Variable {
+    /// Creates a variable with the Actual attribute.
+    fn new(component: &'static str, name: &'static str, data_type: &'static str, mutability: &'static str, value: &str) -> Variable {
+        Variable {
+            component,
+            component_instance: None,
+            evse_id: None,
+            connector_id: None,
+            name,
+            instance: None,
+            attributes: vec![Attribute { attribute_type: "Actual", value: value.to_string(), mutability }],
+            data_type,
+            unit: None,
+            min_limit: None,
+            max_limit: None,
+            values_list: None,
+        }
+    }
+
+    fn instance(mut self, instance: &'static str) -> Variable {
+        self.instance = Some(instance);
+        self
+    }
+
+    fn evse(mut self, evse_id: usize, connector_id: Option<usize>) -> Variable {
+        self.evse_id = Some(evse_id);
+        self.connector_id = connector_id;
+        self
+    }
+
+    fn unit(mut self, unit: &'static str) -> Variable {
+        self.unit = Some(unit);
+        self
+    }
+
+    fn limits(mut self, min_limit: Option<f64>, max_limit: Option<f64>) -> Variable {
+        self.min_limit = min_limit;
+        self.max_limit = max_limit;
+        self
+    }
+
+    fn values_list(mut self, values_list: &'static str) -> Variable {
+        self.values_list = Some(values_list);
+        self
+    }
+
+    fn attribute(mut self, attribute_type: &'static str, mutability: &'static str, value: &str) -> Variable {
+        self.attributes.push(Attribute { attribute_type, value: value.to_string(), mutability });
+        self
+    }
+
+    /// Checks whether a value satisfies data type and constraints of the variable.
+    fn validate(&self, value: &str) -> std::result::Result<(), String> {
+        let within_limits = |number: f64| {
+            self.min_limit.is_none_or(|min| number >= min) && self.max_limit.is_none_or(|max| number <= max)
+        };
+        let in_values_list = |item: &str| self.values_list.is_none_or(|values| values.split(',').any(|v| v == item));
+
+        let valid = match self.data_type {
+            "integer" => value.parse::<i64>().map(|number| within_limits(number as f64)).unwrap_or(false),
+            "decimal" => value.parse::<f64>().map(within_limits).unwrap_or(false),
+            "boolean" => value == "true" || value == "false",
+            "dateTime" => DateTime::parse_from_rfc3339(value).is_ok(),
+            "OptionList" => in_values_list(value),
+            "MemberList" | "SequenceList" => value.is_empty() || value.split(',').all(in_values_list),
+            _ => self.max_limit.is_none_or(|max| value.len() as f64 <= max),
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("{:?} is not a valid {} value of {}.{}", value, self.data_type, self.component, self.name))
+        }
+    }
+}
+
+// Address of a variable in the device model as requested by CSMS.
+#[derive(Clone, Debug)]
+pub struct VariableAddress {
+    pub component: String,
+    pub component_instance: Option<String>,
+    pub evse_id: Option<usize>,
+    pub connector_id: Option<usize>,
+    pub variable: String,
+    pub variable_instance: Option<String>,
+}
+
+impl VariableAddress {
+    /// Reads the address from `component` and `variable` fields of GetVariableData or SetVariableData.
+    pub fn from_json(data: &JsonValue) -> std::result::Result<VariableAddress, CallError> {
+        let component = payload::required_object(data, "component")?;
+        let variable = payload::required_object(data, "variable")?;
+
+        let (evse_id, connector_id) = match payload::optional_object(component, "evse")? {
+            Some(evse) => (
+                Some(payload::required_u64(evse, "id")? as usize),
+                payload::optional_u64(evse, "connectorId")?.map(|connector_id| connector_id as usize),
+            ),
+            None => (None, None),
+        };
+
+        Ok(VariableAddress {
+            component: payload::required_str(component, "name")?.to_string(),
+            component_instance: payload::optional_str(component, "instance")?.map(String::from),
+            evse_id,
+            connector_id,
+            variable: payload::required_str(variable, "name")?.to_string(),
+            variable_instance: payload::optional_str(variable, "instance")?.map(String::from),
+        })
+    }
+
+    fn matches_component(&self, variable: &Variable) -> bool {
+        self.component == variable.component
+            && self.component_instance.as_deref() == variable.component_instance
+            && self.evse_id == variable.evse_id
+            && self.connector_id == variable.connector_id
+    }
+
+    fn matches_variable(&self, variable: &Variable) -> bool {
+        self.matches_component(variable)
+            && self.variable == variable.name
+            && self.variable_instance.as_deref() == variable.instance
+    }
+}
+
+lazy_static! {
+    // Device model: variables of all components of the station.
+    static ref DEVICE_MODEL: Mutex<Vec<Variable>> = Mutex::new(vec![]);
+}
+
+/// Checks whether a connector type is a DC one.
+pub fn is_dc_connector(connector_type: &str) -> bool {
+    matches!(connector_type, "cCCS1" | "cCCS2" | "cG105" | "cTesla" | "cLECCS" | "cChaoJi")
+}
+
+/// Initializes the device model with standard components of a station with the given layout.
+pub fn init(layout: &[Vec<String>]) {
+    let mut variables = vec![
+        Variable::new("ChargingStation", "AvailabilityState", "OptionList", "ReadOnly", "Available").values_list(AVAILABILITY_STATES),
+        Variable::new("ChargingStation", "Available", "boolean", "ReadOnly", "true"),
+        Variable::new("ChargingStation", "SupplyPhases", "integer", "ReadOnly", "3").limits(Some(0.0), Some(3.0)),
+
+        Variable::new("DeviceDataCtrlr", "ItemsPerMessage", "integer", "ReadOnly", "100").instance("GetVariables"),
+        Variable::new("DeviceDataCtrlr", "ItemsPerMessage", "integer", "ReadOnly", "100").instance("SetVariables"),
+
+        Variable::new("OCPPCommCtrlr", "HeartbeatInterval", "integer", "ReadWrite", "0").unit("s").limits(Some(0.0), None),
+        Variable::new("OCPPCommCtrlr", "MessageTimeout", "integer", "ReadWrite", "30").instance("Default").unit("s").limits(Some(1.0), None),
+        Variable::new("OCPPCommCtrlr", "MessageAttempts", "integer", "ReadWrite", "3").instance("TransactionEvent").limits(Some(1.0), None),
+        Variable::new("OCPPCommCtrlr", "MessageAttemptInterval", "integer", "ReadWrite", "10").instance("TransactionEvent").unit("s").limits(Some(0.0), None),
+        Variable::new("OCPPCommCtrlr", "RetryBackOffWaitMinimum", "integer", "ReadWrite", "5").unit("s").limits(Some(0.0), None),
+        Variable::new("OCPPCommCtrlr", "RetryBackOffRandomRange", "integer", "ReadWrite", "5").unit("s").limits(Some(0.0), None),
+        Variable::new("OCPPCommCtrlr", "RetryBackOffRepeatTimes", "integer", "ReadWrite", "5").limits(Some(0.0), None),
+        Variable::new("OCPPCommCtrlr", "OfflineThreshold", "integer", "ReadWrite", "60").unit("s").limits(Some(0.0), None),
+
+        Variable::new("TxCtrlr", "EVConnectionTimeOut", "integer", "ReadWrite", "60").unit("s").limits(Some(0.0), None),
+        Variable::new("TxCtrlr", "StopTxOnEVSideDisconnect", "boolean", "ReadWrite", "true"),
+        Variable::new("TxCtrlr", "StopTxOnInvalidId", "boolean", "ReadWrite", "true"),
+        Variable::new("TxCtrlr", "TxStartPoint", "MemberList", "ReadWrite", "PowerPathClosed").values_list(TX_POINTS),
+        Variable::new("TxCtrlr", "TxStopPoint", "MemberList", "ReadWrite", "EVConnected,Authorized").values_list(TX_POINTS),
+
+        Variable::new("AuthCtrlr", "Enabled", "boolean", "ReadWrite", "true"),
+        Variable::new("AuthCtrlr", "AuthorizeRemoteStart", "boolean", "ReadWrite", "false"),
+        Variable::new("AuthCtrlr", "LocalAuthorizeOffline", "boolean", "ReadWrite", "true"),
+        Variable::new("AuthCtrlr", "LocalPreAuthorize", "boolean", "ReadWrite", "false"),
+        Variable::new("AuthCtrlr", "OfflineTxForUnknownIdEnabled", "boolean", "ReadWrite", "false"),
+
+        Variable::new("AuthCacheCtrlr", "Enabled", "boolean", "ReadWrite", "true"),
+        Variable::new("AuthCacheCtrlr", "Available", "boolean", "ReadOnly", "true"),
+        Variable::new("AuthCacheCtrlr", "LifeTime", "integer", "ReadWrite", "86400").unit("s").limits(Some(0.0), None),
+        Variable::new("AuthCacheCtrlr", "Policy", "OptionList", "ReadWrite", "LRU").values_list("LRU,LFU,FIFO,CUSTOM"),
+        Variable::new("AuthCacheCtrlr", "Storage", "integer", "ReadOnly", "0").unit("B").limits(Some(0.0), Some(authorization::CACHE_STORAGE as f64)),
+
+        Variable::new("LocalAuthListCtrlr", "Enabled", "boolean", "ReadWrite", "true"),
+        Variable::new("LocalAuthListCtrlr", "Available", "boolean", "ReadOnly", "true"),
+        Variable::new("LocalAuthListCtrlr", "ItemsPerMessage", "integer", "ReadOnly", "100"),
+        Variable::new("LocalAuthListCtrlr", "Entries", "integer", "ReadOnly", "0").limits(Some(0.0), Some(authorization::LOCAL_LIST_ENTRIES as f64)),
+
+        Variable::new("ReservationCtrlr", "Enabled", "boolean", "ReadWrite", "true"),
+        Variable::new("ReservationCtrlr", "Available", "boolean", "ReadOnly", "true"),
+        Variable::new("ReservationCtrlr", "NonEvseSpecific", "boolean", "ReadWrite", "true"),
+
+        Variable::new("SmartChargingCtrlr", "Enabled", "boolean", "ReadWrite", "true"),
+        Variable::new("SmartChargingCtrlr", "Available", "boolean", "ReadOnly", "true"),
+        Variable::new("SmartChargingCtrlr", "Entries", "integer", "ReadOnly", "0").instance("ChargingProfiles").limits(Some(0.0), Some(smart_charging::CHARGING_PROFILE_ENTRIES as f64)),
+        Variable::new("SmartChargingCtrlr", "ChargingProfileMaxStackLevel", "integer", "ReadOnly", "10"),
+        Variable::new("SmartChargingCtrlr", "ChargingScheduleChargingRateUnit", "MemberList", "ReadOnly", "A,W").values_list("A,W"),
+        Variable::new("SmartChargingCtrlr", "PeriodsPerSchedule", "integer", "ReadOnly", "24"),
+        Variable::new("SmartChargingCtrlr", "ExternalControlSignalsEnabled", "boolean", "ReadWrite", "true"),
+        Variable::new("SmartChargingCtrlr", "NotifyChargingLimitWithSchedules", "boolean", "ReadWrite", "true"),
+        Variable::new("SmartChargingCtrlr", "LimitChangeSignificance", "decimal", "ReadWrite", "10").unit("Percent").limits(Some(0.0), Some(100.0)),
+
+        Variable::new("LoadBalancingCtrlr", "Enabled", "boolean", "ReadWrite", "false"),
+        Variable::new("LoadBalancingCtrlr", "Limit", "decimal", "ReadWrite", "0").limits(Some(0.0), None),
+        Variable::new("LoadBalancingCtrlr", "LimitUnit", "OptionList", "ReadWrite", "W").values_list("A,W"),
+        Variable::new("LoadBalancingCtrlr", "Policy", "OptionList", "ReadWrite", "Equal").values_list(load_balancing::POLICIES),
+
+        Variable::new("SampledDataCtrlr", "Enabled", "boolean", "ReadWrite", "true"),
+        Variable::new("SampledDataCtrlr", "Available", "boolean", "ReadOnly", "true"),
+        Variable::new("SampledDataCtrlr", "TxStartedMeasurands", "MemberList", "ReadWrite", "Energy.Active.Import.Register").values_list(MEASURANDS),
+        Variable::new("SampledDataCtrlr", "TxUpdatedMeasurands", "MemberList", "ReadWrite", "Energy.Active.Import.Register,Power.Active.Import").values_list(MEASURANDS),
+        Variable::new("SampledDataCtrlr", "TxUpdatedInterval", "integer", "ReadWrite", "0").unit("s").limits(Some(0.0), None),
+        Variable::new("SampledDataCtrlr", "TxEndedMeasurands", "MemberList", "ReadWrite", "Energy.Active.Import.Register").values_list(MEASURANDS),
+        Variable::new("SampledDataCtrlr", "TxEndedInterval", "integer", "ReadWrite", "0").unit("s").limits(Some(0.0), None),
+
+        Variable::new("AlignedDataCtrlr", "Enabled", "boolean", "ReadWrite", "true"),
+        Variable::new("AlignedDataCtrlr", "Available", "boolean", "ReadOnly", "true"),
+        Variable::new("AlignedDataCtrlr", "Measurands", "MemberList", "ReadWrite", "Energy.Active.Import.Register").values_list(MEASURANDS),
+        Variable::new("AlignedDataCtrlr", "Interval", "integer", "ReadWrite", "0").unit("s").limits(Some(0.0), None),
+        Variable::new("AlignedDataCtrlr", "SendDuringIdle", "boolean", "ReadWrite", "false"),
+    ];
+
+    for (evse_index, connector_types) in layout.iter().enumerate() {
+        let evse_id = evse_index + 1;
+        let dc = connector_types.iter().any(|connector_type| is_dc_connector(connector_type));
+        let supply_phases = if dc { "0" } else { "3" };
+        let max_power = if dc { "50000" } else { "22000" };
+
+        variables.push(Variable::new("EVSE", "AvailabilityState", "OptionList", "ReadOnly", "Available").evse(evse_id, None).values_list(AVAILABILITY_STATES));
+        variables.push(Variable::new("EVSE", "Available", "boolean", "ReadOnly", "true").evse(evse_id, None));
+        variables.push(Variable::new("EVSE", "SupplyPhases", "integer", "ReadOnly", supply_phases).evse(evse_id, None).limits(Some(0.0), Some(3.0)));
+        variables.push(Variable::new("EVSE", "Power", "decimal", "ReadOnly", "0").evse(evse_id, None).unit("W").attribute("MaxSet", "ReadOnly", max_power));
+        variables.push(Variable::new("EVSE", "Priority", "integer", "ReadWrite", "0").evse(evse_id, None).limits(Some(0.0), None));
+
+        for (connector_index, connector_type) in connector_types.iter().enumerate() {
+            let connector_id = Some(connector_index + 1);
+            let supply_phases = if is_dc_connector(connector_type) { "0" } else { "3" };
+
+            variables.push(Variable::new("Connector", "AvailabilityState", "OptionList", "ReadOnly", "Available").evse(evse_id, connector_id).values_list(AVAILABILITY_STATES));
+            variables.push(Variable::new("Connector", "Available", "boolean", "ReadOnly", "true").evse(evse_id, connector_id));
+            variables.push(Variable::new("Connector", "ConnectorType", "string", "ReadOnly", connector_type).evse(evse_id, connector_id));
+            variables.push(Variable::new("Connector", "SupplyPhases", "integer", "ReadOnly", supply_phases).evse(evse_id, connector_id).limits(Some(0.0), Some(3.0)));
+            variables.push(Variable::new("ConnectorPlugRetentionLock", "Problem", "boolean", "ReadOnly", "false").evse(evse_id, connector_id));
+        }
+    }
+
+    *DEVICE_MODEL.lock().unwrap() = variables;
+}
+
+/// Returns the current value of a variable which reflects the state of the station rather than a stored value.
+fn live_value(variable: &Variable) -> Option<String> {
+    if variable.component == "EVSE" && variable.name == "Power" {
+        return variable.evse_id
+            .and_then(|evse_id| storage::get_evse(evse_id - 1))
+            .map(|evse| evse.meter.power.round().to_string());
+    }
+
+    if variable.component == "AuthCacheCtrlr" && variable.name == "Storage" {
+        return Some(authorization::cache_size().to_string());
+    }
+
+    if variable.component == "LocalAuthListCtrlr" && variable.name == "Entries" {
+        return Some(authorization::local_list_size().to_string());
     }
+
+    if variable.component == "SmartChargingCtrlr" && variable.name == "Entries" {
+        return Some(smart_charging::profile_count().to_string());
+    }
+
+    if variable.component == "ConnectorPlugRetentionLock" && variable.name == "Problem" {
+        return match (variable.evse_id, variable.connector_id) {
+            (Some(evse_id), Some(connector_id)) => storage::get_connector(evse_id - 1, connector_id - 1).map(|connector| connector.lock_failure.to_string()),
+            _ => None,
+        };
+    }
+
+    if variable.name != "AvailabilityState" {
+        return None;
+    }
+
+    // Map connector statuses to an availability state.
+    let availability_state = |connectors: Vec<storage::Connector>| {
+        let statuses: Vec<&str> = connectors.iter().map(|connector| connector.status).collect();
+
+        if statuses.contains(&"Occupied") {
+            "Occupied"
+        } else if statuses.contains(&"Reserved") {
+            "Reserved"
+        } else if statuses.contains(&"Available") {
+            "Available"
+        } else if statuses.contains(&"Faulted") {
+            "Faulted"
+        } else {
+            "Unavailable"
+        }
+    };
+
+    let connectors: Vec<storage::Connector> = match (variable.evse_id, variable.connector_id) {
+        (Some(evse_id), Some(connector_id)) => storage::get_connector(evse_id - 1, connector_id - 1).into_iter().collect(),
+        (Some(evse_id), None) => storage::get_evse(evse_id - 1).map(|evse| evse.connectors).unwrap_or_default(),
+        _ => storage::get_evses().into_iter().flat_map(|evse| evse.connectors).collect(),
+    };
+
+    Some(availability_state(connectors).to_string())
+}
+
+/// Reads an attribute of a variable requested by CSMS.
+///
+/// Returns the value or GetVariableStatus of the failure.
+pub fn get_variable(address: &VariableAddress, attribute_type: &str) -> std::result::Result<String, &'static str> {
+    let device_model = DEVICE_MODEL.lock().unwrap();
+
+    if !device_model.iter().any(|variable| address.matches_component(variable)) {
+        return Err("UnknownComponent");
+    }
+
+    let variable = match device_model.iter().find(|variable| address.matches_variable(variable)) {
+        Some(res) => res,
+        None => return Err("UnknownVariable"),
+    };
+
+    let attribute = match variable.attributes.iter().find(|attribute| attribute.attribute_type == attribute_type) {
+        Some(res) => res,
+        None => return Err("NotSupportedAttributeType"),
+    };
+
+    if attribute.mutability == "WriteOnly" {
+        return Err("Rejected");
+    }
+
+    if attribute_type == "Actual" {
+        if let Some(value) = live_value(variable) {
+            return Ok(value);
+        }
+    }
+
+    Ok(attribute.value.to_owned())
+}
+
+/// Writes an attribute of a variable requested by CSMS.
+///
+/// Returns SetVariableStatus.
+pub fn set_variable(address: &VariableAddress, attribute_type: &str, value: &str) -> &'static str {
+    write_variable(address, attribute_type, value, true)
+}
+
+/// Sets an initial value of a variable attribute, including a read-only one.
+///
+/// Returns SetVariableStatus.
+pub fn init_variable(address: &VariableAddress, attribute_type: &str, value: &str) -> &'static str {
+    write_variable(address, attribute_type, value, false)
+}
+
+fn write_variable(address: &VariableAddress, attribute_type: &str, value: &str, check_mutability: bool) -> &'static str {
+    let mut device_model = DEVICE_MODEL.lock().unwrap();
+
+    if !device_model.iter().any(|variable| address.matches_component(variable)) {
+        return "UnknownComponent";
+    }
+
+    let variable = match device_model.iter_mut().find(|variable| address.matches_variable(variable)) {
+        Some(res) => res,
+        None => return "UnknownVariable",
+    };
+
+    let attribute_index = match variable.attributes.iter().position(|attribute| attribute.attribute_type == attribute_type) {
+        Some(res) => res,
+        None => return "NotSupportedAttributeType",
+    };
+
+    if check_mutability && variable.attributes[attribute_index].mutability == "ReadOnly" {
+        return "Rejected";
+    }
+
+    if let Err(e) = variable.validate(value) {
+        println!("{}", e);
+        return "Rejected";
+    }
+
+    variable.attributes[attribute_index].value = value.to_string();
+
+    "Accepted"
+}
+
+/// Reads the Actual value of a station-wide variable. Returns an empty string for an unknown variable.
+pub fn get_instance_value(component: &str, variable: &str, instance: Option<&str>) -> String {
+    DEVICE_MODEL.lock().unwrap().iter()
+        .find(|v| v.component == component && v.evse_id.is_none() && v.name == variable && v.instance == instance)
+        .and_then(|v| v.attributes.iter().find(|attribute| attribute.attribute_type == "Actual"))
+        .map(|attribute| attribute.value.to_owned())
+        .unwrap_or_default()
+}
+
+pub fn get_value(component: &str, variable: &str) -> String {
+    get_instance_value(component, variable, None)
+}
+
+pub fn get_bool(component: &str, variable: &str) -> bool {
+    get_value(component, variable) == "true"
+}
+
+pub fn get_instance_integer(component: &str, variable: &str, instance: Option<&str>) -> u64 {
+    get_instance_value(component, variable, instance).parse().unwrap_or(0)
+}
+
+pub fn get_integer(component: &str, variable: &str) -> u64 {
+    get_instance_integer(component, variable, None)
+}
+
+/// Reads an attribute of an EVSE or connector variable. Returns an empty string for an unknown variable.
+pub fn get_evse_value(component: &str, evse_id: usize, connector_id: Option<usize>, variable: &str, attribute_type: &str) -> String {
+    DEVICE_MODEL.lock().unwrap().iter()
+        .find(|v| v.component == component && v.evse_id == Some(evse_id) && v.connector_id == connector_id && v.name == variable)
+        .and_then(|v| v.attributes.iter().find(|attribute| attribute.attribute_type == attribute_type))
+        .map(|attribute| attribute.value.to_owned())
+        .unwrap_or_default()
+}
+
+/// Writes the Actual value of a station-wide variable, regardless of its mutability.
+pub fn set_instance_value(component: &str, variable: &str, instance: Option<&str>, value: &str) {
+    let mut device_model = DEVICE_MODEL.lock().unwrap();
+
+    let attribute = device_model.iter_mut()
+        .find(|v| v.component == component && v.evse_id.is_none() && v.name == variable && v.instance == instance)
+        .and_then(|v| v.attributes.iter_mut().find(|attribute| attribute.attribute_type == "Actual"));
+
+    if let Some(attribute) = attribute {
+        attribute.value = value.to_string();
+    }
+}
+
+pub fn set_value(component: &str, variable: &str, value: &str) {
+    set_instance_value(component, variable, None, value);
 }
@@ -0,0 +1,193 @@
+use chrono::prelude::*;
+use uuid::Uuid;
+
+use crate::requests;
+use crate::reservations;
+use crate::storage;
+
+/// Checks whether a connector can be used for a new transaction.
+///
+/// A reserved connector can be used too, the driver is checked against the reservation once authorized.
+pub fn is_connector_available(evse_index: usize, connector_index: usize) -> bool {
+    storage::is_connector_operational(evse_index, connector_index)
+        && storage::get_connector(evse_index, connector_index).is_some_and(|connector| matches!(connector.status, "Available" | "Reserved"))
+}
+
+/// Returns status of a connector without an EV plugged in.
+pub fn idle_status(evse_index: usize, connector_index: usize) -> &'static str {
+    if !storage::is_connector_operational(evse_index, connector_index) {
+        "Unavailable"
+    } else if reservations::is_connector_reserved(evse_index, connector_index) {
+        "Reserved"
+    } else {
+        "Available"
+    }
+}
+
+/// Returns index of the first connector of an EVSE which can be used for a new transaction.
+pub fn find_available_connector(evse_index: usize) -> Option<usize> {
+    let connector_count = storage::get_evse(evse_index).map_or(0, |evse| evse.connectors.len());
+
+    (0..connector_count).find(|connector_index| is_connector_available(evse_index, *connector_index))
+}
+
+/// Sets status of a connector and sends StatusNotification with the updated status.
+pub fn queue_status_notification(evse_index: usize, connector_index: usize, connector_status: &'static str) {
+    let msg_id: &str = &Uuid::new_v4().to_string();
+    let msg = requests::status_notification(msg_id, evse_index + 1, connector_index + 1, connector_status);
+
+    storage::queue_message(msg_id, msg);
+
+    storage::set_connector_status(evse_index, connector_index, connector_status);
+
+    if let Some(connector) = storage::get_connector(evse_index, connector_index) {
+        println!("EVSE {} connector {} ({}) is {}.", evse_index + 1, connector_index + 1, connector.connector_type, connector.status);
+    }
+}
+
+/// Changes operational status of the station, an EVSE or a connector, and reports connectors which became
+/// available or unavailable.
+///
+/// Occupied connectors change their status once they are released, in which case the change is scheduled.
+/// Returns ChangeAvailabilityStatus.
+pub fn change_availability(evse_index: Option<usize>, connector_index: Option<usize>, operative: bool) -> &'static str {
+    let affected_connectors: Vec<(usize, usize)> = storage::get_evses().iter().enumerate()
+        .filter(|(i, _)| evse_index.is_none_or(|evse_index| evse_index == *i))
+        .flat_map(|(i, evse)| (0..evse.connectors.len())
+            .filter(|j| connector_index.is_none_or(|connector_index| connector_index == *j))
+            .map(move |j| (i, j)))
+        .collect();
+
+    let previously_operational: Vec<bool> = affected_connectors.iter()
+        .map(|(i, j)| storage::is_connector_operational(*i, *j))
+        .collect();
+
+    match (evse_index, connector_index) {
+        (Some(evse_index), Some(connector_index)) => storage::set_connector_operational_status(evse_index, connector_index, operative),
+        (Some(evse_index), None) => storage::set_evse_operational_status(evse_index, operative),
+        _ => storage::set_station_operational_status(operative),
+    }
+
+    // Reservations can't be kept for inoperative connectors.
+    reservations::remove_inoperative();
+
+    let mut scheduled = false;
+
+    for ((i, j), was_operational) in affected_connectors.into_iter().zip(previously_operational) {
+        let operational = storage::is_connector_operational(i, j);
+
+        if operational == was_operational {
+            continue;
+        }
+
+        match storage::get_connector(i, j).map(|connector| connector.status) {
+            Some("Available") | Some("Unavailable") | Some("Reserved") => queue_status_notification(i, j, idle_status(i, j)),
+            Some("Occupied") => scheduled = true,
+            _ => (),
+        }
+    }
+
+    if scheduled { "Scheduled" } else { "Accepted" }
+}
+
+/// Sends NotifyEvent with a change of ConnectorPlugRetentionLock.Problem of a connector.
+fn queue_lock_problem(evse_index: usize, connector_index: usize, problem: bool) {
+    let now = match Utc::now().with_nanosecond(0) {
+        Some(res) => res.to_rfc3339(),
+        None => panic!("Current date is empty."),
+    };
+
+    let event = object!{
+        "eventId" => storage::next_event_id(),
+        "timestamp" => now,
+        "trigger" => "Delta",
+        "actualValue" => problem.to_string(),
+        "cleared" => !problem,
+        "eventNotificationType" => "HardWiredNotification",
+        "component" => object!{
+            "name" => "ConnectorPlugRetentionLock",
+            "evse" => object!{
+                "id" => evse_index + 1,
+                "connectorId" => connector_index + 1,
+            },
+        },
+        "variable" => object!{
+            "name" => "Problem",
+        },
+    };
+
+    let msg_id: &str = &Uuid::new_v4().to_string();
+
+    storage::queue_message(msg_id, requests::notify_event(msg_id, array![event]));
+}
+
+/// Locks the cable in a connector. Returns whether the cable is locked.
+///
+/// A lock failure is reported with NotifyEvent.
+pub fn lock(evse_index: usize, connector_index: usize) -> bool {
+    let connector = match storage::get_connector(evse_index, connector_index) {
+        Some(res) => res,
+        None => return false,
+    };
+
+    if connector.locked {
+        return true;
+    }
+
+    if connector.lock_failure {
+        println!("Cable couldn't be locked in EVSE {} connector {}.", evse_index + 1, connector_index + 1);
+
+        queue_lock_problem(evse_index, connector_index, true);
+
+        return false;
+    }
+
+    storage::set_connector_locked(evse_index, connector_index, true);
+
+    true
+}
+
+/// Unlocks the cable in a connector on request of CSMS. Returns UnlockStatus.
+///
+/// The cable isn't unlocked during an authorized transaction.
+pub fn unlock(evse_index: usize, connector_index: usize) -> &'static str {
+    let connector = match storage::get_connector(evse_index, connector_index) {
+        Some(res) => res,
+        None => return "UnknownConnector",
+    };
+
+    let authorized_transaction = storage::get_evse_transaction(evse_index + 1)
+        .is_some_and(|transaction| transaction.authorized && transaction.connector_id == Some(connector_index + 1));
+
+    if authorized_transaction {
+        return "OngoingAuthorizedTransaction";
+    }
+
+    if connector.lock_failure {
+        return "UnlockFailed";
+    }
+
+    storage::set_connector_locked(evse_index, connector_index, false);
+
+    "Unlocked"
+}
+
+/// Simulates a failure of the cable lock of a connector, or repairs it.
+///
+/// Repair is reported with NotifyEvent.
+pub fn set_lock_failure(evse_index: usize, connector_index: usize, failure: bool) -> Result<(), String> {
+    let connector = match storage::get_connector(evse_index, connector_index) {
+        Some(res) => res,
+        None => return Err(format!("Connector {} of EVSE {} doesn't exist", connector_index + 1, evse_index + 1)),
+    };
+
+    storage::set_connector_lock_failure(evse_index, connector_index, failure);
+
+    if connector.lock_failure && !failure {
+        queue_lock_problem(evse_index, connector_index, false);
+    }
+
+    println!("Cable lock of EVSE {} connector {} is {}.", evse_index + 1, connector_index + 1, if failure { "broken" } else { "repaired" });
+
+    Ok(())
+}
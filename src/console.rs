@@ -0,0 +1,124 @@
+use std::io::{self, BufRead};
+use std::thread;
+
+use crate::authorization;
+use crate::connectors;
+use crate::smart_charging;
+use crate::transactions;
+
+const HELP: &str = "Commands:
+  plug <evseId> [connectorId]  Plug the EV in a connector
+  unplug <evseId>              Unplug the EV from an EVSE
+  authorize <evseId> <idToken> [type]
+                               Present an idToken at an EVSE, ISO14443 by default
+  lock-failure <evseId> <connectorId> <on|off>
+                               Break or repair the cable lock of a connector
+  limit <evseId> <W> [source]  Set an external limit of an EVSE, or the station for EVSE 0, EMS by default
+  clear-limit <evseId> [source]
+                               Release external limits of an EVSE, or the station for EVSE 0
+  help                         Show this help";
+
+/// Parses an EVSE or connector ID into an index.
+fn parse_index(argument: Option<&str>, name: &str) -> Result<usize, String> {
+    match argument.map(|argument| argument.parse::<usize>()) {
+        Some(Ok(res)) if res > 0 => Ok(res - 1),
+        Some(_) => Err(format!("{} must be a positive integer", name)),
+        None => Err(format!("{} is missing", name)),
+    }
+}
+
+/// Parses an EVSE ID, which is 0 for the whole station.
+fn parse_evse_id(argument: Option<&str>) -> Result<usize, String> {
+    match argument.map(|argument| argument.parse::<usize>()) {
+        Some(Ok(res)) => Ok(res),
+        Some(_) => Err("EVSE ID must be a non-negative integer".to_string()),
+        None => Err("EVSE ID is missing".to_string()),
+    }
+}
+
+/// Executes a single console command.
+fn execute(line: &str) -> Result<(), String> {
+    let mut arguments = line.split_whitespace();
+
+    let command = match arguments.next() {
+        Some(res) => res,
+        None => return Ok(()),
+    };
+
+    match command {
+        "plug" => {
+            let evse_index = parse_index(arguments.next(), "EVSE ID")?;
+            let connector_index = match arguments.next() {
+                Some(res) => parse_index(Some(res), "Connector ID")?,
+                None => 0,
+            };
+
+            transactions::plug_in(evse_index, connector_index)
+        },
+        "unplug" => {
+            let evse_index = parse_index(arguments.next(), "EVSE ID")?;
+
+            transactions::unplug(evse_index)
+        },
+        "authorize" => {
+            let evse_index = parse_index(arguments.next(), "EVSE ID")?;
+            let value = arguments.next().ok_or("IdToken is missing")?;
+            let token_type = arguments.next().unwrap_or("ISO14443");
+
+            if !authorization::ID_TOKEN_TYPES.contains(&token_type) {
+                return Err(format!("IdToken type must be one of {}", authorization::ID_TOKEN_TYPES.join(", ")));
+            }
+
+            authorization::present(evse_index, authorization::id_token(value, token_type))
+        },
+        "lock-failure" => {
+            let evse_index = parse_index(arguments.next(), "EVSE ID")?;
+            let connector_index = parse_index(arguments.next(), "Connector ID")?;
+            let failure = match arguments.next() {
+                Some("on") => true,
+                Some("off") => false,
+                _ => return Err("Lock failure must be \"on\" or \"off\"".to_string()),
+            };
+
+            connectors::set_lock_failure(evse_index, connector_index, failure)
+        },
+        "limit" => {
+            let evse_id = parse_evse_id(arguments.next())?;
+            let limit = match arguments.next().map(|argument| argument.parse::<f64>()) {
+                Some(Ok(res)) => res,
+                Some(_) => return Err("Limit must be a number".to_string()),
+                None => return Err("Limit is missing".to_string()),
+            };
+            let source = arguments.next().unwrap_or("EMS");
+
+            smart_charging::set_external_limit(evse_id, limit, source)
+        },
+        "clear-limit" => {
+            let evse_id = parse_evse_id(arguments.next())?;
+
+            smart_charging::clear_external_limit(evse_id, arguments.next())
+        },
+        "help" => {
+            println!("{}", HELP);
+
+            Ok(())
+        },
+        _ => Err(format!("Unknown command {:?}, type \"help\" for the list of commands", command)),
+    }
+}
+
+/// Starts reading commands which simulate driver actions from the standard input.
+pub fn start() {
+    thread::spawn(|| {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(res) => res,
+                Err(_) => break,
+            };
+
+            if let Err(e) = execute(&line) {
+                println!("Command failed ({}).", e);
+            }
+        }
+    });
+}
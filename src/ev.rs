@@ -0,0 +1,172 @@
+use std::sync::Mutex;
+
+use json::JsonValue;
+
+// Share of the maximum power which the EV still draws at the end of the taper, so that charging completes.
+const MIN_TAPER_POWER: f64 = 0.05;
+
+// Electric vehicle connected to an EVSE.
+#[derive(Clone, Debug)]
+pub struct Ev {
+    // Usable battery capacity, in Wh.
+    pub battery_capacity: f64,
+    // State of charge, in percent.
+    pub soc: f64,
+    // State of charge at which the EV stops charging, in percent.
+    pub target_soc: f64,
+    // Maximum power the EV accepts from AC and DC EVSEs, in W.
+    pub max_ac_power: f64,
+    pub max_dc_power: f64,
+    // State of charge at which constant current phase ends and power tapers off, in percent.
+    pub taper_soc: f64,
+}
+
+impl Default for Ev {
+    fn default() -> Ev {
+        Ev {
+            battery_capacity: 60000.0,
+            soc: 20.0,
+            target_soc: 80.0,
+            max_ac_power: 11000.0,
+            max_dc_power: 100000.0,
+            taper_soc: 80.0,
+        }
+    }
+}
+
+impl Ev {
+    /// Reads an EV from a JSON object. Fields absent in the object keep their values.
+    pub fn update_from_json(&mut self, data: &JsonValue) -> Result<(), String> {
+        let fields: [(&str, &mut f64); 6] = [
+            ("batteryCapacity", &mut self.battery_capacity),
+            ("soc", &mut self.soc),
+            ("targetSoC", &mut self.target_soc),
+            ("maxAcPower", &mut self.max_ac_power),
+            ("maxDcPower", &mut self.max_dc_power),
+            ("taperSoC", &mut self.taper_soc),
+        ];
+
+        for (field, value) in fields {
+            if data[field].is_null() {
+                continue;
+            }
+
+            match data[field].as_f64() {
+                Some(res) if res >= 0.0 => *value = res,
+                _ => return Err(format!("Field \"{}\" must be a non-negative number", field)),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        object!{
+            "batteryCapacity" => self.battery_capacity,
+            "soc" => self.soc,
+            "targetSoC" => self.target_soc,
+            "maxAcPower" => self.max_ac_power,
+            "maxDcPower" => self.max_dc_power,
+            "taperSoC" => self.taper_soc,
+        }
+    }
+
+    /// Checks whether the EV reached its target state of charge.
+    pub fn is_full(&self) -> bool {
+        self.soc >= self.target_soc.min(100.0)
+    }
+
+    /// Returns power the EV draws at its current state of charge, in W.
+    ///
+    /// The EV draws its maximum power up to `taper_soc` (constant current), after that power
+    /// decreases linearly towards the full battery (constant voltage).
+    pub fn power_limit(&self, dc: bool) -> f64 {
+        if self.is_full() {
+            return 0.0;
+        }
+
+        let max_power = if dc { self.max_dc_power } else { self.max_ac_power };
+
+        if self.soc < self.taper_soc || self.taper_soc >= 100.0 {
+            return max_power;
+        }
+
+        let taper = (100.0 - self.soc) / (100.0 - self.taper_soc);
+
+        max_power * taper.max(MIN_TAPER_POWER)
+    }
+
+    /// Stores energy delivered to the battery, in Wh.
+    pub fn charge(&mut self, energy: f64) {
+        if self.battery_capacity > 0.0 {
+            self.soc = (self.soc + energy / self.battery_capacity * 100.0).min(100.0);
+        }
+    }
+}
+
+lazy_static! {
+    // EV which is connected when a transaction starts.
+    static ref TEMPLATE: Mutex<Ev> = Mutex::new(Ev::default());
+}
+
+pub fn set_template(value: Ev) {
+    *TEMPLATE.lock().unwrap() = value;
+}
+
+/// Returns a new EV with the configured parameters.
+pub fn new_ev() -> Ev {
+    TEMPLATE.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_tapers_off_after_taper_soc() {
+        let mut ev = Ev { soc: 50.0, target_soc: 100.0, taper_soc: 80.0, ..Ev::default() };
+
+        assert_eq!(ev.power_limit(false), 11000.0);
+        assert_eq!(ev.power_limit(true), 100000.0);
+
+        ev.soc = 90.0;
+        assert_eq!(ev.power_limit(false), 5500.0);
+
+        // Close to the full battery the EV keeps drawing a small share, so that charging completes.
+        ev.soc = 99.5;
+        assert_eq!(ev.power_limit(false), 11000.0 * MIN_TAPER_POWER);
+    }
+
+    #[test]
+    fn ev_stops_at_target_soc() {
+        let mut ev = Ev { soc: 79.0, target_soc: 80.0, taper_soc: 90.0, ..Ev::default() };
+
+        assert!(!ev.is_full());
+        assert_eq!(ev.power_limit(false), 11000.0);
+
+        // 1 % of 60 kWh.
+        ev.charge(600.0);
+
+        assert!(ev.is_full());
+        assert_eq!(ev.power_limit(false), 0.0);
+
+        // Target above 100 % means the full battery.
+        let ev = Ev { soc: 100.0, target_soc: 120.0, ..Ev::default() };
+
+        assert!(ev.is_full());
+    }
+
+    #[test]
+    fn charge_doesnt_exceed_full_battery() {
+        let mut ev = Ev { soc: 95.0, ..Ev::default() };
+
+        ev.charge(6000.0);
+        assert_eq!(ev.soc, 100.0);
+
+        // Without a battery capacity the state of charge is unknown, so it doesn't change.
+        let mut ev = Ev { battery_capacity: 0.0, ..Ev::default() };
+
+        ev.charge(1000.0);
+        assert_eq!(ev.soc, 20.0);
+    }
+}
@@ -0,0 +1,186 @@
+use std::sync::Mutex;
+
+use chrono::prelude::*;
+
+use crate::components;
+use crate::meter;
+use crate::smart_charging;
+use crate::storage;
+
+// Values of LoadBalancingCtrlr.Policy.
+pub const POLICIES: &str = "Equal,FirstCome,Priority";
+// Lowest current an EV charges with, in A per phase. EVSEs which can't be given as much are suspended.
+const MIN_CURRENT: f64 = 6.0;
+
+lazy_static! {
+    // Policy, shared limit and EVSEs which shared it last time, to report changes.
+    static ref SHARING: Mutex<(String, f64, Vec<usize>)> = Mutex::new((String::new(), 0.0, vec![]));
+}
+
+/// Returns the limit of the whole station which is shared among EVSEs, in W, if load balancing is enabled.
+///
+/// The configured limit is capped by external constraints and ChargingStationMaxProfile of the station.
+fn site_limit() -> Option<f64> {
+    if !components::get_bool("LoadBalancingCtrlr", "Enabled") {
+        return None;
+    }
+
+    let limit: f64 = components::get_value("LoadBalancingCtrlr", "Limit").parse().unwrap_or(0.0);
+
+    let limit = if components::get_value("LoadBalancingCtrlr", "LimitUnit") == "A" {
+        meter::to_watts(None, limit, None)
+    } else {
+        limit
+    };
+
+    Some(smart_charging::station_limit(Utc::now()).map_or(limit, |station_limit| station_limit.min(limit)))
+}
+
+/// Returns indices of EVSEs in the order they are served: by the start of their transactions, and by their
+/// priority first with the Priority policy.
+fn serving_order(evse_indices: Vec<usize>, by_priority: bool) -> Vec<usize> {
+    let mut order: Vec<(u64, u64, usize)> = evse_indices.into_iter()
+        .map(|evse_index| {
+            let priority: u64 = if by_priority { components::get_evse_value("EVSE", evse_index + 1, None, "Priority", "Actual").parse().unwrap_or(0) } else { 0 };
+            let started_at = storage::get_evse_transaction(evse_index + 1).map_or(u64::MAX, |transaction| transaction.started_at);
+
+            (u64::MAX - priority, started_at, evse_index)
+        })
+        .collect();
+
+    order.sort_unstable();
+
+    order.into_iter().map(|(_, _, evse_index)| evse_index).collect()
+}
+
+/// Shares a limit among EVSEs which draw power, given the power EVs would draw at each EVSE, the least power they
+/// can be given and the order EVSEs which draw power are served in. Returns the share of every EVSE, in W.
+///
+/// EVSEs served last get no power if the limit doesn't cover the least power of all of them. Equal policy gives
+/// the others the same share, and the share an EV doesn't use goes to the others. FirstCome and Priority policies
+/// give every EVSE all it can use in their order, keeping the least power for the rest.
+fn share(limit: f64, policy: &str, demands: &[f64], floors: &[f64], order: &[usize]) -> Vec<f64> {
+    let floor = |evse_index: usize| floors[evse_index].min(demands[evse_index]);
+
+    let mut served = order.to_vec();
+
+    while served.iter().map(|evse_index| floor(*evse_index)).sum::<f64>() > limit {
+        served.pop();
+    }
+
+    let mut allocations = vec![0.0; demands.len()];
+    let mut remaining = limit;
+
+    match policy {
+        "FirstCome" | "Priority" => {
+            for (i, evse_index) in served.iter().enumerate() {
+                let reserved: f64 = served[i + 1..].iter().map(|evse_index| floor(*evse_index)).sum();
+
+                allocations[*evse_index] = demands[*evse_index].min(remaining - reserved);
+                remaining -= allocations[*evse_index];
+            }
+        },
+        _ => {
+            // EVSEs which need less than their share leave the rest to the others.
+            let mut by_demand = served.to_owned();
+
+            by_demand.sort_by(|a, b| demands[*a].total_cmp(&demands[*b]));
+
+            for (i, evse_index) in by_demand.iter().enumerate() {
+                let share = remaining / (by_demand.len() - i) as f64;
+
+                allocations[*evse_index] = demands[*evse_index].min(share).max(floor(*evse_index));
+                remaining -= allocations[*evse_index];
+            }
+        },
+    }
+
+    allocations
+}
+
+/// Shares the limit of the station among EVSEs which draw power, given the power EVs would draw at each EVSE.
+/// Returns the limit of every EVSE in W, or nothing if load balancing is disabled.
+///
+/// EVSEs are served by the start of their transactions, and by their priority first with Priority policy.
+/// Every EVSE which is served gets at least MIN_CURRENT. EVSEs which don't draw power are offered the share
+/// they would get once they do.
+pub fn allocate(demands: &[f64]) -> Option<Vec<f64>> {
+    let site_limit = site_limit()?;
+
+    let active: Vec<usize> = (0..demands.len()).filter(|evse_index| demands[*evse_index] > 0.0).collect();
+
+    let policy = components::get_value("LoadBalancingCtrlr", "Policy");
+    let by_priority = policy == "Priority";
+    let floors: Vec<f64> = (0..demands.len()).map(|evse_index| meter::to_watts(Some(evse_index), MIN_CURRENT, None)).collect();
+
+    let mut allocations = share(site_limit, &policy, demands, &floors, &serving_order(active.to_owned(), by_priority));
+
+    for evse_index in (0..demands.len()).filter(|evse_index| !active.contains(evse_index)) {
+        let mut joined = active.to_owned();
+        let mut joined_demands = demands.to_vec();
+
+        joined.push(evse_index);
+        joined_demands[evse_index] = meter::offered_power(evse_index);
+
+        allocations[evse_index] = share(site_limit, &policy, &joined_demands, &floors, &serving_order(joined, by_priority))[evse_index];
+    }
+
+    let mut sharing = SHARING.lock().unwrap();
+
+    // Shares are reported when EVSEs start or stop drawing power, or the policy or the limit changes.
+    if !active.is_empty() && (sharing.0 != policy || sharing.1 != site_limit || sharing.2 != active) {
+        let shares: Vec<String> = active.iter()
+            .map(|evse_index| format!("EVSE {} {} W", evse_index + 1, meter::round(allocations[*evse_index])))
+            .collect();
+
+        println!("Load balancing ({}) shares {} W: {}.", policy, meter::round(site_limit), shares.join(", "));
+    }
+
+    *sharing = (policy, site_limit, active);
+
+    Some(allocations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Least power of a three-phase AC EVSE.
+    const FLOOR: f64 = 6.0 * 230.0 * 3.0;
+
+    #[test]
+    fn equal_policy_splits_limit_evenly() {
+        let allocations = share(21000.0, "Equal", &[11000.0, 11000.0, 11000.0], &[FLOOR; 3], &[0, 1, 2]);
+
+        assert_eq!(allocations, vec![7000.0, 7000.0, 7000.0]);
+
+        // The share an EV doesn't use goes to the others.
+        let allocations = share(21000.0, "Equal", &[5000.0, 11000.0, 11000.0], &[FLOOR; 3], &[0, 1, 2]);
+
+        assert_eq!(allocations, vec![5000.0, 8000.0, 8000.0]);
+    }
+
+    #[test]
+    fn station_limit_below_evse_limits_is_shared() {
+        let demands = [11000.0, 11000.0, 0.0];
+
+        assert_eq!(share(15000.0, "Equal", &demands, &[FLOOR; 3], &[0, 1]), vec![7500.0, 7500.0, 0.0]);
+        assert_eq!(share(15000.0, "FirstCome", &demands, &[FLOOR; 3], &[1, 0]), vec![FLOOR, 15000.0 - FLOOR, 0.0]);
+        assert_eq!(share(30000.0, "FirstCome", &demands, &[FLOOR; 3], &[0, 1]), vec![11000.0, 11000.0, 0.0]);
+    }
+
+    #[test]
+    fn evses_get_at_least_minimum_current() {
+        let demands = [11000.0, 11000.0, 11000.0];
+
+        // The limit covers the least power of two EVSEs, the one served last is suspended.
+        assert_eq!(share(10000.0, "Equal", &demands, &[FLOOR; 3], &[2, 0, 1]), vec![5000.0, 0.0, 5000.0]);
+        assert_eq!(share(10000.0, "Priority", &demands, &[FLOOR; 3], &[2, 0, 1]), vec![FLOOR, 0.0, 10000.0 - FLOOR]);
+
+        // EVs which draw less than the least power need only what they draw.
+        assert_eq!(share(6000.0, "Equal", &[1000.0, 11000.0], &[FLOOR; 2], &[0, 1]), vec![1000.0, 5000.0]);
+        assert_eq!(share(5000.0, "Equal", &[1000.0, 11000.0], &[FLOOR; 2], &[0, 1]), vec![1000.0, 0.0]);
+
+        assert_eq!(share(FLOOR - 1.0, "FirstCome", &demands, &[FLOOR; 3], &[0, 1, 2]), vec![0.0, 0.0, 0.0]);
+    }
+}
@@ -6,29 +6,96 @@ extern crate time;
 #[macro_use]
 extern crate json;
 extern crate chrono;
-extern crate queues;
+extern crate rand;
 
 use std::env;
+use std::thread;
 
 use ws::{connect};
 
+mod payload;
 mod requests;
 mod responses;
 mod components;
+mod ev;
+mod profile;
+mod persistence;
 mod storage;
+mod connectors;
+mod transactions;
+mod authorization;
+mod reservations;
+mod smart_charging;
+mod load_balancing;
+mod meter;
+mod console;
 mod client;
+mod reconnect;
 
 /// Station configuration struct.
 #[derive(Debug)]
 struct Config {
     csms_url: String,
     station_id: String,
+    profile: Option<String>,
+    data_dir: Option<String>,
+}
+
+/// Reads an optional environment variable. Empty variables are ignored.
+fn env_string(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|var| !var.is_empty())
+}
+
+/// Reads an optional numeric environment variable.
+fn env_number(name: &str) -> Option<u64> {
+    env_string(name).map(|var| match var.parse() {
+        Ok(res) => res,
+        Err(e) => panic!("Couldn't parse {} ({})", name, e),
+    })
+}
+
+/// Reads the path of a profile file from `--profile` command line flag.
+fn profile_flag() -> Option<String> {
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == "--profile" {
+            match args.next() {
+                Some(res) => return Some(res),
+                None => panic!("Couldn't read --profile (path is missing)"),
+            }
+        }
+
+        if let Some(res) = arg.strip_prefix("--profile=") {
+            return Some(res.to_string());
+        }
+    }
+
+    None
+}
+
+/// Parses station layout.
+///
+/// EVSEs are separated by semicolons, connector types of an EVSE are separated by commas,
+/// e.g. `cCCS2,cG105;cCCS2,cG105` describes two EVSEs with CCS and CHAdeMO connectors.
+fn parse_evses(layout: &str) -> Vec<Vec<String>> {
+    layout.split(';').map(|evse| {
+        evse.split(',').map(|connector_type| {
+            let connector_type = connector_type.trim();
+
+            if connector_type.is_empty() {
+                panic!("Couldn't parse EVSES ({:?} has an empty connector type)", layout);
+            }
+
+            connector_type.to_string()
+        }).collect()
+    }).collect()
 }
 
 /// Starts a charging station.
 ///
-/// Initializes configuration variables from the environment.
-/// Starts a WebSocket client.
+/// Loads a station profile and overrides its fields with configuration variables from the environment.
+/// Starts a WebSocket client and reconnects it with a back-off whenever the connection is lost.
 fn main() {
     dotenv::dotenv().expect("Failed to read .env file");
 
@@ -43,17 +110,123 @@ fn main() {
     };
 
     let config = Config {
-        csms_url: csms_url,
-        station_id: station_id,
+        csms_url,
+        station_id,
+        // The command line flag takes precedence over the environment.
+        profile: profile_flag().or_else(|| env_string("PROFILE")),
+        data_dir: env_string("DATA_DIR"),
+    };
+
+    let mut profile = match &config.profile {
+        Some(path) => profile::load(path),
+        None => profile::Profile::default(),
     };
 
+    // Environment overrides fields of the profile.
+    if let Some(model) = env_string("MODEL") {
+        profile.charging_station.model = model;
+    }
+
+    if let Some(vendor_name) = env_string("VENDOR_NAME") {
+        profile.charging_station.vendor_name = vendor_name;
+    }
+
+    if let Some(serial_number) = env_string("SERIAL_NUMBER") {
+        profile.charging_station.serial_number = Some(serial_number);
+    }
+
+    if let Some(firmware_version) = env_string("FIRMWARE_VERSION") {
+        profile.charging_station.firmware_version = Some(firmware_version);
+    }
+
+    if let Some(evses) = env_string("EVSES") {
+        profile.evses = parse_evses(&evses);
+    }
+
+    let env_ev = object!{
+        "batteryCapacity" => env_number("EV_BATTERY_CAPACITY"),
+        "soc" => env_number("EV_SOC"),
+        "targetSoC" => env_number("EV_TARGET_SOC"),
+        "maxAcPower" => env_number("EV_MAX_AC_POWER"),
+        "maxDcPower" => env_number("EV_MAX_DC_POWER"),
+    };
+
+    if let Err(e) = profile.ev.update_from_json(&env_ev) {
+        panic!("Couldn't read EV configuration ({})", e);
+    }
+
     println!("OCPP version: 2.0");
     println!("CSMS url: {:?}", config.csms_url);
     println!("Station id: {:?}", config.station_id);
+    println!("Profile: {:?}", config.profile);
+    println!("Charging station: {:?}", profile.charging_station);
+    println!("EVSEs: {:?}", profile.evses);
+    println!("EV: {:?}", profile.ev);
+
+    storage::init_evses(&profile.evses);
+    components::init(&profile.evses);
+    profile::apply_variables(&profile);
+    profile::set_charging_station(profile.charging_station);
+    ev::set_template(profile.ev);
+
+    // Environment overrides variables of the profile.
+    let env_variables = [
+        ("RETRY_BACK_OFF_WAIT_MINIMUM", "RetryBackOffWaitMinimum", None),
+        ("RETRY_BACK_OFF_RANDOM_RANGE", "RetryBackOffRandomRange", None),
+        ("RETRY_BACK_OFF_REPEAT_TIMES", "RetryBackOffRepeatTimes", None),
+        ("MESSAGE_TIMEOUT", "MessageTimeout", Some("Default")),
+        ("MESSAGE_ATTEMPTS", "MessageAttempts", Some("TransactionEvent")),
+        ("MESSAGE_ATTEMPT_INTERVAL", "MessageAttemptInterval", Some("TransactionEvent")),
+    ];
+
+    for (env_name, variable, instance) in env_variables.iter() {
+        if let Some(value) = env_number(env_name) {
+            components::set_instance_value("OCPPCommCtrlr", variable, *instance, &value.to_string());
+        }
+    }
+
+    if let Some(data_dir) = &config.data_dir {
+        println!("Data directory: {:?}", data_dir);
+
+        persistence::init(data_dir);
+        storage::restore();
+    }
 
     let mut connection_string: String = config.csms_url.to_owned();
-    connection_string.push_str("/");
+    connection_string.push('/');
     connection_string.push_str(&config.station_id);
 
-    connect(connection_string, |out| { client::Client { out: out } }).unwrap()
+    if let Err(e) = url::Url::parse(&connection_string) {
+        panic!("Couldn't parse CSMS_URL ({})", e);
+    }
+
+    meter::start();
+    console::start();
+    reservations::start();
+
+    let mut backoff = reconnect::Backoff::new();
+
+    // Reconnect whenever the connection is closed or can't be established.
+    loop {
+        let connection_count = storage::connection_count();
+
+        let client_factory = |out| client::Client { out };
+
+        if let Err(e) = connect(connection_string.to_owned(), client_factory) {
+            println!("WebSocket error: {:?}", e);
+        }
+
+        storage::set_connected(false);
+
+        // Start back-off over if the connection was open.
+        if storage::connection_count() != connection_count {
+            backoff.reset();
+        }
+
+        let delay = backoff.next_delay();
+
+        println!("Reconnecting to CSMS in {:.1} s.", delay.as_secs_f64());
+
+        thread::sleep(delay);
+    }
 }
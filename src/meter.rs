@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use json::JsonValue;
+use uuid::Uuid;
+
+use crate::components;
+use crate::load_balancing;
+use crate::requests;
+use crate::smart_charging;
+use crate::storage;
+use crate::transactions;
+
+// Interval between meter updates, in milliseconds.
+const TICK_INTERVAL: u64 = 1000;
+// Voltage of AC and DC EVSEs, in V.
+const AC_VOLTAGE: f64 = 230.0;
+const DC_VOLTAGE: f64 = 400.0;
+
+/// Rounds a sampled or converted value to one decimal.
+pub fn round(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+/// Returns the current time in the format of OCPP messages.
+fn now() -> String {
+    match Utc::now().with_nanosecond(0) {
+        Some(res) => res.to_rfc3339(),
+        None => panic!("Current date is empty."),
+    }
+}
+
+/// Samples the meter of an EVSE.
+///
+/// Returns a list with a single MeterValue which has a sampled value for every supported measurand
+/// of the comma separated list, or an empty list if there is nothing to sample.
+pub fn sample(evse_index: usize, measurands: &str, context: &str) -> JsonValue {
+    let mut meter_value = JsonValue::new_array();
+
+    let meter = match storage::get_evse(evse_index) {
+        Some(evse) => evse.meter,
+        None => return meter_value,
+    };
+
+    let mut sampled_values = JsonValue::new_array();
+
+    for measurand in measurands.split(',').filter(|measurand| !measurand.is_empty()) {
+        let (value, unit, location) = match measurand {
+            "Energy.Active.Import.Register" => (meter.energy, "Wh", "Outlet"),
+            "Power.Active.Import" => (meter.power, "W", "Outlet"),
+            "Power.Offered" => (meter.power_offered, "W", "Outlet"),
+            "Current.Import" => (meter.current, "A", "Outlet"),
+            "Voltage" => (meter.voltage, "V", "Outlet"),
+            "SoC" => match meter.soc {
+                Some(soc) => (soc, "Percent", "EV"),
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        sampled_values.push(object!{
+            "value" => round(value),
+            "context" => context,
+            "measurand" => measurand,
+            "location" => location,
+            "unitOfMeasure" => object!{ "unit" => unit },
+        }).unwrap();
+    }
+
+    if !sampled_values.is_empty() {
+        meter_value.push(object!{
+            "timestamp" => now(),
+            "sampledValue" => sampled_values,
+        }).unwrap();
+    }
+
+    meter_value
+}
+
+/// Samples the meter of an EVSE for a TransactionEvent. Returns an empty list if sampled data is disabled.
+pub fn sample_transaction(evse_index: usize, measurands_variable: &str, context: &str) -> JsonValue {
+    if !components::get_bool("SampledDataCtrlr", "Enabled") {
+        return JsonValue::new_array();
+    }
+
+    sample(evse_index, &components::get_value("SampledDataCtrlr", measurands_variable), context)
+}
+
+/// Returns number of phases supplying an EVSE, or the whole station if no EVSE is given. DC is supplied with 0 phases.
+pub fn supply_phases(evse_index: Option<usize>) -> u64 {
+    match evse_index {
+        Some(evse_index) => components::get_evse_value("EVSE", evse_index + 1, None, "SupplyPhases", "Actual"),
+        None => components::get_value("ChargingStation", "SupplyPhases"),
+    }.parse().unwrap_or(0)
+}
+
+/// Converts current to power in W, given the number of supply phases.
+///
+/// AC current is per phase and flows in the given number of phases, up to the supply phases.
+fn current_to_power(supply_phases: u64, current: f64, number_phases: Option<u64>) -> f64 {
+    if supply_phases == 0 {
+        return current * DC_VOLTAGE;
+    }
+
+    current * AC_VOLTAGE * number_phases.unwrap_or(supply_phases).min(supply_phases) as f64
+}
+
+/// Converts power in W to current, given the number of supply phases.
+///
+/// AC current is per phase, flowing in all supply phases.
+fn power_to_current(supply_phases: u64, power: f64) -> f64 {
+    if supply_phases == 0 {
+        return power / DC_VOLTAGE;
+    }
+
+    power / (AC_VOLTAGE * supply_phases as f64)
+}
+
+/// Converts current to power in W for an EVSE, or the whole station if no EVSE is given.
+pub fn to_watts(evse_index: Option<usize>, current: f64, number_phases: Option<u64>) -> f64 {
+    current_to_power(supply_phases(evse_index), current, number_phases)
+}
+
+/// Converts power in W to current for an EVSE, or the whole station if no EVSE is given.
+pub fn to_amps(evse_index: Option<usize>, power: f64) -> f64 {
+    power_to_current(supply_phases(evse_index), power)
+}
+
+/// Returns maximum power of an EVSE, or the whole station if no EVSE is given, in W.
+pub fn max_power(evse_index: Option<usize>) -> f64 {
+    let evse_ids: Vec<usize> = match evse_index {
+        Some(evse_index) => vec![evse_index + 1],
+        None => (1..=storage::get_evses().len()).collect(),
+    };
+
+    evse_ids.into_iter()
+        .map(|evse_id| components::get_evse_value("EVSE", evse_id, None, "Power", "MaxSet").parse().unwrap_or(0.0))
+        .sum()
+}
+
+/// Returns power an EVSE can offer to the EV, in W: the EVSE limit capped by charging profiles.
+pub fn offered_power(evse_index: usize) -> f64 {
+    let profile_limit = smart_charging::evse_limit(evse_index, Utc::now()).unwrap_or(f64::INFINITY);
+
+    max_power(Some(evse_index)).min(profile_limit)
+}
+
+/// Returns power the EV connected to an EVSE would draw, in W: the lower of the EV limit and the power
+/// the EVSE offers.
+pub fn power_limit(evse_index: usize) -> f64 {
+    let ev = match storage::get_evse(evse_index).and_then(|evse| evse.ev) {
+        Some(res) => res,
+        None => return 0.0,
+    };
+
+    ev.power_limit(supply_phases(Some(evse_index)) == 0).min(offered_power(evse_index))
+}
+
+/// Updates the meter and the connected EV of an EVSE with energy delivered during the last tick.
+///
+/// The EV draws the given power during the next tick, up to the offered power.
+fn update(evse_index: usize, elapsed: f64, power: f64, power_offered: f64) {
+    let evse_id = evse_index + 1;
+
+    let energy = match storage::get_evse(evse_index) {
+        Some(evse) => evse.meter.power * elapsed / 3600.0,
+        None => return,
+    };
+
+    storage::update_ev(evse_index, |ev| ev.charge(energy));
+
+    let soc = storage::get_evse(evse_index).and_then(|evse| evse.ev).map(|ev| ev.soc);
+
+    let phases: f64 = components::get_evse_value("EVSE", evse_id, None, "SupplyPhases", "Actual").parse().unwrap_or(0.0);
+    let voltage = if phases > 0.0 { AC_VOLTAGE } else { DC_VOLTAGE };
+
+    let power = power.min(power_offered);
+
+    storage::update_meter(evse_index, |meter| {
+        meter.energy += energy;
+        meter.power = power;
+        meter.power_offered = power_offered;
+        meter.voltage = voltage;
+        meter.current = power / voltage / phases.max(1.0);
+        meter.soc = soc;
+    });
+}
+
+// State of periodic sampling.
+struct Sampler {
+    // When the meter was updated last time, in milliseconds.
+    last_tick: u64,
+    // Number of the clock aligned interval of the last tick, if clock aligned data is configured.
+    last_aligned_interval: Option<u64>,
+    // When TxUpdated and TxEnded values were sampled last time for each transaction, in seconds.
+    last_updated_samples: HashMap<String, u64>,
+    last_ended_samples: HashMap<String, u64>,
+    // Shares of EVSEs when load balancing shared the station limit last time, in W.
+    last_allocations: Vec<f64>,
+}
+
+impl Sampler {
+    /// Updates meters and sends sampled data which is due.
+    fn tick(&mut self) {
+        let current_timestamp = Utc::now().timestamp_millis() as u64;
+        let elapsed = current_timestamp.saturating_sub(self.last_tick) as f64 / 1000.0;
+        let current_second = current_timestamp / 1000;
+
+        self.last_tick = current_timestamp;
+
+        // Clock aligned data.
+        let aligned_interval = current_second.checked_div(components::get_integer("AlignedDataCtrlr", "Interval"));
+        let aligned_data_due = components::get_bool("AlignedDataCtrlr", "Enabled")
+            && self.last_aligned_interval.is_some()
+            && aligned_interval != self.last_aligned_interval;
+
+        self.last_aligned_interval = aligned_interval;
+
+        let tx_updated_interval = components::get_integer("SampledDataCtrlr", "TxUpdatedInterval");
+        let tx_ended_interval = components::get_integer("SampledDataCtrlr", "TxEndedInterval");
+
+        let transactions = storage::get_transactions();
+
+        self.last_updated_samples.retain(|transaction_id, _| transactions.iter().any(|transaction| &transaction.id == transaction_id));
+        self.last_ended_samples.retain(|transaction_id, _| transactions.iter().any(|transaction| &transaction.id == transaction_id));
+
+        let evse_count = storage::get_evses().len();
+
+        // While the power path is closed, the EV draws power up to its limit, shared by load balancing.
+        let demands: Vec<f64> = (0..evse_count)
+            .map(|evse_index| if transactions::is_power_path_closed(evse_index) { power_limit(evse_index) } else { 0.0 })
+            .collect();
+
+        let allocations = load_balancing::allocate(&demands);
+
+        // Shares which changed significantly are reported once meters of their EVSEs are updated.
+        let rebalanced: Vec<bool> = (0..evse_count)
+            .map(|evse_index| match (&allocations, self.last_allocations.get(evse_index)) {
+                (Some(allocations), Some(last_allocation)) => smart_charging::is_significant_change(Some(*last_allocation), Some(allocations[evse_index])),
+                _ => false,
+            })
+            .collect();
+
+        self.last_allocations = allocations.clone().unwrap_or_default();
+
+        for evse_index in 0..evse_count {
+            let power_offered = match &allocations {
+                Some(allocations) => allocations[evse_index].min(offered_power(evse_index)),
+                None => offered_power(evse_index),
+            };
+
+            update(evse_index, elapsed, demands[evse_index], power_offered);
+
+            transactions::update(evse_index, elapsed);
+
+            if rebalanced[evse_index] {
+                transactions::report_meter_values(evse_index, "ChargingRateChanged", sample(evse_index, "Power.Offered", "Other"));
+            }
+
+            let transaction_id = match storage::get_evse_transaction(evse_index + 1).filter(|transaction| transaction.started) {
+                Some(res) => res.id,
+                None => {
+                    // Meter values which are not related to a transaction.
+                    if aligned_data_due {
+                        let meter_value = sample(evse_index, &components::get_value("AlignedDataCtrlr", "Measurands"), "Sample.Clock");
+
+                        if !meter_value.is_empty() {
+                            let msg_id: &str = &Uuid::new_v4().to_string();
+
+                            storage::queue_message(msg_id, requests::meter_values(msg_id, evse_index + 1, meter_value));
+                        }
+                    }
+
+                    continue;
+                },
+            };
+
+            // Clock aligned meter values during a transaction, unless they are sent only while idle.
+            if aligned_data_due && !components::get_bool("AlignedDataCtrlr", "SendDuringIdle") {
+                let meter_value = sample(evse_index, &components::get_value("AlignedDataCtrlr", "Measurands"), "Sample.Clock");
+
+                if !meter_value.is_empty() {
+                    transactions::report_meter_values(evse_index, "MeterValueClock", meter_value);
+                }
+            }
+
+            if tx_updated_interval > 0 {
+                let last_sample = *self.last_updated_samples.entry(transaction_id.to_owned()).or_insert(current_second);
+
+                if current_second >= last_sample + tx_updated_interval {
+                    self.last_updated_samples.insert(transaction_id.to_owned(), current_second);
+
+                    let meter_value = sample_transaction(evse_index, "TxUpdatedMeasurands", "Sample.Periodic");
+
+                    if !meter_value.is_empty() {
+                        transactions::report_meter_values(evse_index, "MeterValuePeriodic", meter_value);
+                    }
+                }
+            }
+
+            if tx_ended_interval > 0 {
+                let last_sample = *self.last_ended_samples.entry(transaction_id.to_owned()).or_insert(current_second);
+
+                if current_second >= last_sample + tx_ended_interval {
+                    self.last_ended_samples.insert(transaction_id.to_owned(), current_second);
+
+                    // Values are kept with the transaction until it ends.
+                    transactions::add_meter_values(evse_index, sample_transaction(evse_index, "TxEndedMeasurands", "Sample.Periodic"));
+                }
+            }
+        }
+
+        // Meter readings change on every tick, they are saved in intervals.
+        storage::persist_changes();
+    }
+}
+
+/// Starts the simulation of EVSE meters.
+///
+/// Meters keep running while the station is offline, sampled data is queued until it can be sent.
+pub fn start() {
+    let current_timestamp = Utc::now().timestamp_millis() as u64;
+
+    let mut sampler = Sampler {
+        last_tick: current_timestamp,
+        last_aligned_interval: (current_timestamp / 1000).checked_div(components::get_integer("AlignedDataCtrlr", "Interval")),
+        last_updated_samples: HashMap::new(),
+        last_ended_samples: HashMap::new(),
+        last_allocations: vec![],
+    };
+
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_millis(TICK_INTERVAL));
+
+        sampler.tick();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_is_converted_to_power() {
+        assert_eq!(current_to_power(3, 16.0, None), 16.0 * 230.0 * 3.0);
+        assert_eq!(current_to_power(0, 100.0, None), 100.0 * 400.0);
+
+        // Current flows in the given number of phases, up to the supply phases.
+        assert_eq!(current_to_power(3, 16.0, Some(1)), 16.0 * 230.0);
+        assert_eq!(current_to_power(3, 16.0, Some(4)), 16.0 * 230.0 * 3.0);
+        assert_eq!(current_to_power(1, 32.0, Some(3)), 32.0 * 230.0);
+    }
+
+    #[test]
+    fn power_is_converted_to_current() {
+        assert_eq!(power_to_current(3, 11040.0), 16.0);
+        assert_eq!(power_to_current(1, 7360.0), 32.0);
+        assert_eq!(power_to_current(0, 50000.0), 125.0);
+    }
+}
@@ -0,0 +1,175 @@
+use chrono::prelude::*;
+use json::JsonValue;
+
+/// Error which is reported to CSMS with a CALLERROR message.
+#[derive(Clone, Debug)]
+pub struct CallError {
+    pub code: &'static str,
+    pub description: String,
+}
+
+impl CallError {
+    pub fn new(code: &'static str, description: &str) -> CallError {
+        CallError {
+            code,
+            description: description.to_string(),
+        }
+    }
+}
+
+/// Error for a required field which is absent in the payload.
+fn missing(field: &str) -> CallError {
+    CallError::new("ProtocolError", &format!("Required field \"{}\" is missing", field))
+}
+
+/// Error for a field which has a value of a wrong type.
+fn wrong_type(field: &str, expected: &str) -> CallError {
+    CallError::new("TypeConstraintViolation", &format!("Field \"{}\" must be {}", field, expected))
+}
+
+/// Reads an optional non-negative integer field.
+pub fn optional_u64(payload: &JsonValue, field: &str) -> Result<Option<u64>, CallError> {
+    let value = &payload[field];
+
+    if value.is_null() {
+        return Ok(None);
+    }
+
+    match (value.as_u64(), value.as_f64()) {
+        (Some(res), Some(number)) if number.fract() == 0.0 => Ok(Some(res)),
+        _ => Err(wrong_type(field, "a non-negative integer")),
+    }
+}
+
+/// Reads a required non-negative integer field.
+pub fn required_u64(payload: &JsonValue, field: &str) -> Result<u64, CallError> {
+    match optional_u64(payload, field)? {
+        Some(res) => Ok(res),
+        None => Err(missing(field)),
+    }
+}
+
+/// Reads an optional number field.
+pub fn optional_f64(payload: &JsonValue, field: &str) -> Result<Option<f64>, CallError> {
+    let value = &payload[field];
+
+    if value.is_null() {
+        return Ok(None);
+    }
+
+    match value.as_f64() {
+        Some(res) => Ok(Some(res)),
+        None => Err(wrong_type(field, "a number")),
+    }
+}
+
+/// Reads a required number field.
+pub fn required_f64(payload: &JsonValue, field: &str) -> Result<f64, CallError> {
+    match optional_f64(payload, field)? {
+        Some(res) => Ok(res),
+        None => Err(missing(field)),
+    }
+}
+
+/// Reads a required string field.
+pub fn required_str<'a>(payload: &'a JsonValue, field: &str) -> Result<&'a str, CallError> {
+    let value = &payload[field];
+
+    if value.is_null() {
+        return Err(missing(field));
+    }
+
+    match value.as_str() {
+        Some(res) => Ok(res),
+        None => Err(wrong_type(field, "a string")),
+    }
+}
+
+/// Reads a required array field.
+pub fn required_array<'a>(payload: &'a JsonValue, field: &str) -> Result<&'a JsonValue, CallError> {
+    let value = &payload[field];
+
+    if value.is_null() {
+        return Err(missing(field));
+    }
+
+    if !value.is_array() {
+        return Err(wrong_type(field, "an array"));
+    }
+
+    Ok(value)
+}
+
+/// Reads an optional array field. A missing array is empty.
+pub fn optional_array(payload: &JsonValue, field: &str) -> Result<JsonValue, CallError> {
+    if payload[field].is_null() {
+        return Ok(JsonValue::new_array());
+    }
+
+    required_array(payload, field).cloned()
+}
+
+/// Reads an optional string field.
+pub fn optional_str<'a>(payload: &'a JsonValue, field: &str) -> Result<Option<&'a str>, CallError> {
+    if payload[field].is_null() {
+        return Ok(None);
+    }
+
+    required_str(payload, field).map(Some)
+}
+
+/// Reads an optional date and time field in RFC 3339 format.
+pub fn optional_date_time(payload: &JsonValue, field: &str) -> Result<Option<DateTime<Utc>>, CallError> {
+    match optional_str(payload, field)?.map(DateTime::parse_from_rfc3339) {
+        Some(Ok(res)) => Ok(Some(res.with_timezone(&Utc))),
+        Some(Err(_)) => Err(wrong_type(field, "a date and time")),
+        None => Ok(None),
+    }
+}
+
+/// Reads a required date and time field in RFC 3339 format.
+pub fn required_date_time(payload: &JsonValue, field: &str) -> Result<DateTime<Utc>, CallError> {
+    match optional_date_time(payload, field)? {
+        Some(res) => Ok(res),
+        None => Err(missing(field)),
+    }
+}
+
+/// Reads an optional string field which must have one of the given values.
+pub fn optional_enum<'a>(payload: &'a JsonValue, field: &str, values: &[&str]) -> Result<Option<&'a str>, CallError> {
+    match optional_str(payload, field)? {
+        Some(res) if !values.contains(&res) => Err(CallError::new("PropertyConstraintViolation", &format!("Field \"{}\" must be one of {}", field, values.join(", ")))),
+        res => Ok(res),
+    }
+}
+
+/// Reads a required string field which must have one of the given values.
+pub fn required_enum<'a>(payload: &'a JsonValue, field: &str, values: &[&str]) -> Result<&'a str, CallError> {
+    match optional_enum(payload, field, values)? {
+        Some(res) => Ok(res),
+        None => Err(missing(field)),
+    }
+}
+
+/// Reads an optional object field.
+pub fn optional_object<'a>(payload: &'a JsonValue, field: &str) -> Result<Option<&'a JsonValue>, CallError> {
+    let value = &payload[field];
+
+    if value.is_null() {
+        return Ok(None);
+    }
+
+    if !value.is_object() {
+        return Err(wrong_type(field, "an object"));
+    }
+
+    Ok(Some(value))
+}
+
+/// Reads a required object field.
+pub fn required_object<'a>(payload: &'a JsonValue, field: &str) -> Result<&'a JsonValue, CallError> {
+    match optional_object(payload, field)? {
+        Some(res) => Ok(res),
+        None => Err(missing(field)),
+    }
+}
@@ -0,0 +1,64 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use json::JsonValue;
+
+// Name of the file with saved station state.
+const STATE_FILE: &str = "state.json";
+
+lazy_static! {
+    // Directory where station state is saved. Persistence is disabled when it's not set.
+    static ref DATA_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// Enables persistence of station state in the given directory.
+pub fn init(data_dir: &str) {
+    let path = PathBuf::from(data_dir);
+
+    if let Err(e) = fs::create_dir_all(&path) {
+        panic!("Couldn't create data directory {:?} ({})", path, e);
+    }
+
+    *DATA_DIR.lock().unwrap() = Some(path);
+}
+
+/// Reads saved station state.
+///
+/// Returns `None` if persistence is disabled or no state was saved yet.
+pub fn load() -> Option<JsonValue> {
+    let path = DATA_DIR.lock().unwrap().as_ref()?.join(STATE_FILE);
+
+    let data = match fs::read_to_string(&path) {
+        Ok(res) => res,
+        Err(_) => return None,
+    };
+
+    match json::parse(&data) {
+        Ok(res) => Some(res),
+        Err(e) => {
+            println!("Couldn't parse saved state {:?} ({:?}), starting with a clean state.", path, e);
+            None
+        },
+    }
+}
+
+/// Saves station state built by the given function.
+///
+/// The state is built while the data directory is locked, so that concurrent saves
+/// are written in the same order as the state changes.
+pub fn save<F: FnOnce() -> JsonValue>(snapshot: F) {
+    let data_dir = DATA_DIR.lock().unwrap();
+
+    let path = match data_dir.as_ref() {
+        Some(res) => res.join(STATE_FILE),
+        None => return,
+    };
+
+    // Write to a temporary file first, so that a crash never leaves a partially written state.
+    let tmp_path = path.with_extension("json.tmp");
+
+    if let Err(e) = fs::write(&tmp_path, snapshot().dump()).and_then(|_| fs::rename(&tmp_path, &path)) {
+        println!("Couldn't save state to {:?} ({})", path, e);
+    }
+}
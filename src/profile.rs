@@ -0,0 +1,154 @@
+use std::fs;
+use std::sync::Mutex;
+
+use json::JsonValue;
+
+use crate::components;
+use crate::ev::Ev;
+use crate::payload;
+
+// Identity of the station which is reported in BootNotification.
+#[derive(Clone, Debug)]
+pub struct ChargingStation {
+    pub model: String,
+    pub vendor_name: String,
+    pub serial_number: Option<String>,
+    pub firmware_version: Option<String>,
+    // SIM card of the modem.
+    pub modem_iccid: Option<String>,
+    pub modem_imsi: Option<String>,
+}
+
+// Station "personality": identity, layout, initial values of the device model and the simulated EV.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    pub charging_station: ChargingStation,
+    // Connector types of each EVSE.
+    pub evses: Vec<Vec<String>>,
+    // Initial variable values in the form of SetVariableData.
+    pub variables: Vec<JsonValue>,
+    // EV which is connected when a transaction starts.
+    pub ev: Ev,
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile {
+            charging_station: ChargingStation {
+                model: "Model".to_string(),
+                vendor_name: "Vendor name".to_string(),
+                serial_number: None,
+                firmware_version: None,
+                modem_iccid: None,
+                modem_imsi: None,
+            },
+            evses: vec![vec!["cType2".to_string()]],
+            variables: vec![],
+            ev: Ev::default(),
+        }
+    }
+}
+
+lazy_static! {
+    // Identity of the station.
+    static ref CHARGING_STATION: Mutex<Option<ChargingStation>> = Mutex::new(None);
+}
+
+/// Reads an optional string field of a profile.
+fn optional_string(data: &JsonValue, field: &str, path: &str) -> Option<String> {
+    match payload::optional_str(data, field) {
+        Ok(res) => res.map(String::from),
+        Err(e) => panic!("Couldn't load profile {:?} ({})", path, e.description),
+    }
+}
+
+/// Loads a profile from a JSON file.
+///
+/// Fields absent in the file keep their default values.
+pub fn load(path: &str) -> Profile {
+    let data = match fs::read_to_string(path) {
+        Ok(res) => res,
+        Err(e) => panic!("Couldn't read profile {:?} ({})", path, e),
+    };
+
+    let parsed_profile = match json::parse(&data) {
+        Ok(res) => res,
+        Err(e) => panic!("Couldn't parse profile {:?} ({})", path, e),
+    };
+
+    let mut profile = Profile::default();
+
+    let charging_station = &parsed_profile["chargingStation"];
+
+    if let Some(model) = optional_string(charging_station, "model", path) {
+        profile.charging_station.model = model;
+    }
+
+    if let Some(vendor_name) = optional_string(charging_station, "vendorName", path) {
+        profile.charging_station.vendor_name = vendor_name;
+    }
+
+    profile.charging_station.serial_number = optional_string(charging_station, "serialNumber", path);
+    profile.charging_station.firmware_version = optional_string(charging_station, "firmwareVersion", path);
+    profile.charging_station.modem_iccid = optional_string(&charging_station["modem"], "iccid", path);
+    profile.charging_station.modem_imsi = optional_string(&charging_station["modem"], "imsi", path);
+
+    if !parsed_profile["evses"].is_null() {
+        profile.evses = parsed_profile["evses"].members().map(|evse| {
+            let connector_types: Vec<String> = evse["connectors"].members().map(|connector| match connector["type"].as_str() {
+                Some(res) => res.to_string(),
+                None => panic!("Couldn't load profile {:?} (connector has no type)", path),
+            }).collect();
+
+            if connector_types.is_empty() {
+                panic!("Couldn't load profile {:?} (EVSE has no connectors)", path);
+            }
+
+            connector_types
+        }).collect();
+
+        if profile.evses.is_empty() {
+            panic!("Couldn't load profile {:?} (no EVSEs)", path);
+        }
+    }
+
+    profile.variables = parsed_profile["variables"].members().cloned().collect();
+
+    if let Err(e) = profile.ev.update_from_json(&parsed_profile["ev"]) {
+        panic!("Couldn't load profile {:?} ({})", path, e);
+    }
+
+    profile
+}
+
+/// Sets initial values of the device model from a profile.
+///
+/// Has to be called after the device model is initialized.
+pub fn apply_variables(profile: &Profile) {
+    for variable in profile.variables.iter() {
+        let address = match components::VariableAddress::from_json(variable) {
+            Ok(res) => res,
+            Err(e) => panic!("Couldn't apply profile variable {} ({})", variable, e.description),
+        };
+
+        let attribute_type = variable["attributeType"].as_str().unwrap_or("Actual");
+        let attribute_value: &str = &variable["attributeValue"].to_string();
+
+        let attribute_status = components::init_variable(&address, attribute_type, attribute_value);
+
+        if attribute_status != "Accepted" {
+            panic!("Couldn't apply profile variable {} ({})", variable, attribute_status);
+        }
+    }
+}
+
+pub fn set_charging_station(value: ChargingStation) {
+    *CHARGING_STATION.lock().unwrap() = Some(value);
+}
+
+pub fn get_charging_station() -> ChargingStation {
+    match CHARGING_STATION.lock().unwrap().clone() {
+        Some(res) => res,
+        None => Profile::default().charging_station,
+    }
+}
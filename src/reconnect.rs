@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::components;
+
+/// Back-off between reconnection attempts.
+///
+/// Follows OCPPCommCtrlr `RetryBackOff*` variables: the first attempt waits `RetryBackOffWaitMinimum`
+/// seconds plus a random delay up to `RetryBackOffRandomRange` seconds, every next attempt doubles the
+/// wait time, up to `RetryBackOffRepeatTimes` times, after which the wait time stays the same.
+#[derive(Debug)]
+pub struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new() -> Backoff {
+        Backoff {
+            attempt: 0,
+        }
+    }
+
+    /// Starts over after a successful connection.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    /// Returns the delay before the next reconnection attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let wait_minimum = components::get_integer("OCPPCommCtrlr", "RetryBackOffWaitMinimum");
+        let random_range = components::get_integer("OCPPCommCtrlr", "RetryBackOffRandomRange");
+        let repeat_times = components::get_integer("OCPPCommCtrlr", "RetryBackOffRepeatTimes").min(u64::from(u32::MAX)) as u32;
+
+        let wait_time = wait_minimum.saturating_mul(1 << self.attempt.min(repeat_times).min(16));
+        let jitter = rand::thread_rng().gen_range(0, random_range.saturating_mul(1000).saturating_add(1));
+
+        if self.attempt < repeat_times {
+            self.attempt += 1;
+        }
+
+        Duration::from_millis(wait_time.saturating_mul(1000).saturating_add(jitter))
+    }
+}
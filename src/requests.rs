@@ -1,5 +1,8 @@
 use chrono::prelude::*;
-use json::stringify;
+use json::{stringify, JsonValue};
+
+use crate::profile::ChargingStation;
+use crate::transactions::Transaction;
 
 // OCPP constant.
 const CALL: u8 = 2;
@@ -9,25 +12,36 @@ fn wrap_call(msg_id: &str, action: &str, payload: &str) -> String {
     format!("[{}, \"{}\", \"{}\", {}]", CALL, msg_id, action, payload)
 }
 
-pub fn boot_notification(msg_id: &str, reason: &str, model: &str, vendor_name: &str, serial_number: Option<String>) -> String {
+pub fn boot_notification(msg_id: &str, reason: &str, charging_station: &ChargingStation) -> String {
     let action = "BootNotification";
     let mut payload = object!{
         "reason" => reason,
         "chargingStation" => object!{
-            "model" => model,
-            "vendorName" => vendor_name,
+            "model" => charging_station.model.as_str(),
+            "vendorName" => charging_station.vendor_name.as_str(),
         },
     };
 
-    match serial_number {
-        Some(data) => payload["chargingStation"]["serialNumber"] = data.into(),
-        _ => (),
-    };
+    if let Some(data) = &charging_station.serial_number {
+        payload["chargingStation"]["serialNumber"] = data.as_str().into();
+    }
+
+    if let Some(data) = &charging_station.firmware_version {
+        payload["chargingStation"]["firmwareVersion"] = data.as_str().into();
+    }
+
+    if let Some(data) = &charging_station.modem_iccid {
+        payload["chargingStation"]["modem"]["iccid"] = data.as_str().into();
+    }
+
+    if let Some(data) = &charging_station.modem_imsi {
+        payload["chargingStation"]["modem"]["imsi"] = data.as_str().into();
+    }
 
     wrap_call(msg_id, action, &stringify(payload))
 }
 
-pub fn status_notification(msg_id: &str, evse_id: u8, connector_id: u8, status: &str) -> String {
+pub fn status_notification(msg_id: &str, evse_id: usize, connector_id: usize, status: &str) -> String {
     let action = "StatusNotification";
     let now = match Utc::now().with_nanosecond(0) {
         Some(res) => res.to_rfc3339(),
@@ -50,7 +64,10 @@ pub fn heartbeat(msg_id: &str) -> String {
     wrap_call(msg_id, action, payload)
 }
 
-pub fn transaction_event(msg_id: &str, transaction_id: &str, event_type: &str, trigger_reason: &str, charging_state: Option<&str>, remote_start_id: Option<u64>, stopped_reason: Option<&str>) -> String {
+/// Builds TransactionEvent request with the current state of a transaction.
+///
+/// EVSE and idToken are included until they were reported once.
+pub fn transaction_event(msg_id: &str, transaction: &Transaction, event_type: &str, trigger_reason: &str, offline: bool, meter_value: JsonValue) -> String {
     let action = "TransactionEvent";
     let now = match Utc::now().with_nanosecond(0) {
         Some(res) => res.to_rfc3339(),
@@ -60,26 +77,157 @@ pub fn transaction_event(msg_id: &str, transaction_id: &str, event_type: &str, t
         "eventType" => event_type,
         "timestamp" => now,
         "triggerReason" => trigger_reason,
-        "seqNo" => 0,
+        "seqNo" => transaction.seq_no,
         "transactionData" => object!{
-            "id" => transaction_id,
+            "id" => transaction.id.as_str(),
+            "chargingState" => transaction.charging_state.as_str(),
+            "timeSpentCharging" => transaction.time_spent_charging.round() as u64,
         },
     };
 
-    match charging_state {
-        Some(data) => payload["transactionData"]["chargingState"] = data.into(),
-        _ => (),
+    if let Some(data) = transaction.remote_start_id {
+        payload["transactionData"]["remoteStartId"] = data.into();
+    }
+
+    if let Some(data) = transaction.reservation_id {
+        payload["reservationId"] = data.into();
+    }
+
+    if let Some(data) = &transaction.stopped_reason {
+        payload["transactionData"]["stoppedReason"] = data.as_str().into();
+    }
+
+    if let (Some(data), false) = (&transaction.id_token, transaction.id_token_sent) {
+        payload["idToken"] = data.clone();
+    }
+
+    if !transaction.evse_sent {
+        payload["evse"] = object!{
+            "id" => transaction.evse_id,
+        };
+
+        if let Some(data) = transaction.connector_id {
+            payload["evse"]["connectorId"] = data.into();
+        }
+    }
+
+    // Events which happened while the station was offline are marked.
+    if offline {
+        payload["offline"] = true.into();
+    }
+
+    if !meter_value.is_empty() {
+        payload["meterValue"] = meter_value;
+    }
+
+    wrap_call(msg_id, action, &stringify(payload))
+}
+
+pub fn meter_values(msg_id: &str, evse_id: usize, meter_value: JsonValue) -> String {
+    let action = "MeterValues";
+    let payload = object!{
+        "evseId" => evse_id,
+        "meterValue" => meter_value,
     };
 
-    match remote_start_id {
-        Some(data) => payload["transactionData"]["remoteStartId"] = data.into(),
-        _ => (),
+    wrap_call(msg_id, action, &stringify(payload))
+}
+
+pub fn authorize(msg_id: &str, id_token: &JsonValue) -> String {
+    let action = "Authorize";
+    let payload = object!{
+        "idToken" => id_token.clone(),
     };
 
-    match stopped_reason {
-        Some(data) => payload["transactionData"]["stoppedReason"] = data.into(),
-        _ => (),
+    wrap_call(msg_id, action, &stringify(payload))
+}
+
+pub fn notify_event(msg_id: &str, event_data: JsonValue) -> String {
+    let action = "NotifyEvent";
+    let now = match Utc::now().with_nanosecond(0) {
+        Some(res) => res.to_rfc3339(),
+        None => panic!("Current date is empty."),
     };
+    let payload = object!{
+        "generatedAt" => now,
+        "seqNo" => 0,
+        "eventData" => event_data,
+    };
+
+    wrap_call(msg_id, action, &stringify(payload))
+}
+
+pub fn log_status_notification(msg_id: &str, status: &str) -> String {
+    let action = "LogStatusNotification";
+    let payload = object!{
+        "status" => status,
+    };
+
+    wrap_call(msg_id, action, &stringify(payload))
+}
+
+pub fn firmware_status_notification(msg_id: &str, status: &str) -> String {
+    let action = "FirmwareStatusNotification";
+    let payload = object!{
+        "status" => status,
+    };
+
+    wrap_call(msg_id, action, &stringify(payload))
+}
+
+pub fn reservation_status_update(msg_id: &str, reservation_id: u64, status: &str) -> String {
+    let action = "ReservationStatusUpdate";
+    let payload = object!{
+        "reservationId" => reservation_id,
+        "reservationUpdateStatus" => status,
+    };
+
+    wrap_call(msg_id, action, &stringify(payload))
+}
+
+pub fn report_charging_profiles(msg_id: &str, request_id: u64, charging_limit_source: &str, tbc: bool, evse_id: usize, charging_profiles: JsonValue) -> String {
+    let action = "ReportChargingProfiles";
+    let payload = object!{
+        "requestId" => request_id,
+        "chargingLimitSource" => charging_limit_source,
+        "tbc" => tbc,
+        "evseId" => evse_id,
+        "chargingProfile" => charging_profiles,
+    };
+
+    wrap_call(msg_id, action, &stringify(payload))
+}
+
+pub fn notify_charging_limit(msg_id: &str, charging_limit_source: &str, is_grid_critical: bool, evse_id: usize, charging_schedules: Option<JsonValue>) -> String {
+    let action = "NotifyChargingLimit";
+    let mut payload = object!{
+        "chargingLimit" => object!{
+            "chargingLimitSource" => charging_limit_source,
+            "isGridCritical" => is_grid_critical,
+        },
+    };
+
+    // Limit of the whole station has no EVSE.
+    if evse_id > 0 {
+        payload["evseId"] = evse_id.into();
+    }
+
+    if let Some(data) = charging_schedules {
+        payload["chargingSchedule"] = data;
+    }
+
+    wrap_call(msg_id, action, &stringify(payload))
+}
+
+pub fn cleared_charging_limit(msg_id: &str, charging_limit_source: &str, evse_id: usize) -> String {
+    let action = "ClearedChargingLimit";
+    let mut payload = object!{
+        "chargingLimitSource" => charging_limit_source,
+    };
+
+    if evse_id > 0 {
+        payload["evseId"] = evse_id.into();
+    }
 
     wrap_call(msg_id, action, &stringify(payload))
 }
@@ -0,0 +1,264 @@
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use json::JsonValue;
+use uuid::Uuid;
+
+use crate::authorization;
+use crate::components;
+use crate::connectors;
+use crate::requests;
+use crate::storage;
+
+// Interval between checks of reservation expiry, in milliseconds.
+const EXPIRY_CHECK_INTERVAL: u64 = 1000;
+
+// Reservation of an EVSE for a driver, made with ReserveNow.
+#[derive(Clone, Debug)]
+pub struct Reservation {
+    pub id: u64,
+    pub evse_id: usize,
+    // Reserved connector type. All connectors of the EVSE are reserved if it's not given.
+    pub connector_type: Option<String>,
+    pub id_token: JsonValue,
+    // Drivers of the same group may use the reservation too.
+    pub group_id_token: Option<JsonValue>,
+    pub expiry_date_time: DateTime<Utc>,
+}
+
+impl Reservation {
+    pub fn to_json(&self) -> JsonValue {
+        object!{
+            "id" => self.id,
+            "evseId" => self.evse_id,
+            "connectorType" => self.connector_type.clone(),
+            "idToken" => self.id_token.clone(),
+            "groupIdToken" => self.group_id_token.clone(),
+            "expiryDateTime" => self.expiry_date_time.to_rfc3339(),
+        }
+    }
+
+    pub fn from_json(data: &JsonValue) -> Option<Reservation> {
+        Some(Reservation {
+            id: data["id"].as_u64()?,
+            evse_id: data["evseId"].as_usize()?,
+            connector_type: data["connectorType"].as_str().map(String::from),
+            id_token: Some(data["idToken"].clone()).filter(|id_token| id_token.is_object())?,
+            group_id_token: Some(data["groupIdToken"].clone()).filter(|group_id_token| group_id_token.is_object()),
+            expiry_date_time: DateTime::parse_from_rfc3339(data["expiryDateTime"].as_str()?).ok()?.with_timezone(&Utc),
+        })
+    }
+
+    /// Checks whether a connector of the reserved EVSE is reserved.
+    pub fn covers(&self, connector: &storage::Connector) -> bool {
+        self.connector_type.as_ref().is_none_or(|connector_type| *connector_type == connector.connector_type)
+    }
+}
+
+/// Sends ReservationStatusUpdate with a reservation which ended without being used.
+fn queue_status_update(reservation_id: u64, status: &str) {
+    let msg_id: &str = &Uuid::new_v4().to_string();
+
+    storage::queue_message(msg_id, requests::reservation_status_update(msg_id, reservation_id, status));
+}
+
+/// Sets reserved connectors of an EVSE free once its reservation is gone.
+fn release(evse_index: usize) {
+    let connectors = match storage::get_evse(evse_index) {
+        Some(evse) => evse.connectors,
+        None => return,
+    };
+
+    for (connector_index, connector) in connectors.iter().enumerate() {
+        if connector.status == "Reserved" {
+            connectors::queue_status_notification(evse_index, connector_index, connectors::idle_status(evse_index, connector_index));
+        }
+    }
+}
+
+/// Checks whether a connector is reserved.
+pub fn is_connector_reserved(evse_index: usize, connector_index: usize) -> bool {
+    match (storage::get_evse_reservation(evse_index + 1), storage::get_connector(evse_index, connector_index)) {
+        (Some(reservation), Some(connector)) => reservation.covers(&connector),
+        _ => false,
+    }
+}
+
+/// Determines whether an EVSE can be reserved. Returns ReserveNowStatus.
+///
+/// The reservation with the given ID doesn't prevent the EVSE from being reserved, as it's going to be replaced.
+fn evse_status(evse_index: usize, connector_type: Option<&str>, reservation_id: u64) -> &'static str {
+    let evse_id = evse_index + 1;
+
+    let connector_indices: Vec<usize> = match storage::get_evse(evse_index) {
+        Some(evse) => (0..evse.connectors.len())
+            .filter(|i| connector_type.is_none_or(|connector_type| evse.connectors[*i].connector_type == connector_type))
+            .collect(),
+        None => return "Rejected",
+    };
+
+    if connector_indices.is_empty() {
+        return "Rejected";
+    }
+
+    if !connector_indices.iter().any(|connector_index| storage::is_connector_operational(evse_index, *connector_index)) {
+        return "Unavailable";
+    }
+
+    let reserved = storage::get_evse_reservation(evse_id).is_some_and(|reservation| reservation.id != reservation_id);
+
+    if reserved || storage::get_evse_transaction(evse_id).is_some() {
+        return "Occupied";
+    }
+
+    "Accepted"
+}
+
+/// Reserves an EVSE for a driver until the expiry date. Returns ReserveNowStatus.
+///
+/// A reservation without an EVSE is made for the first EVSE which can accept it. A reservation with
+/// the ID of an existing one replaces it.
+pub fn reserve(id: u64, evse_index: Option<usize>, connector_type: Option<&str>, id_token: JsonValue, group_id_token: Option<JsonValue>, expiry_date_time: DateTime<Utc>) -> &'static str {
+    if !components::get_bool("ReservationCtrlr", "Enabled") || !components::get_bool("ReservationCtrlr", "Available") {
+        return "Rejected";
+    }
+
+    if evse_index.is_none() && !components::get_bool("ReservationCtrlr", "NonEvseSpecific") {
+        return "Rejected";
+    }
+
+    if expiry_date_time <= Utc::now() {
+        return "Rejected";
+    }
+
+    let statuses: Vec<(usize, &str)> = (0..storage::get_evses().len())
+        .filter(|i| evse_index.is_none_or(|evse_index| evse_index == *i))
+        .map(|i| (i, evse_status(i, connector_type, id)))
+        .collect();
+
+    let evse_index = match statuses.iter().find(|(_, status)| *status == "Accepted") {
+        Some((res, _)) => *res,
+        None => {
+            // Report why the closest match can't be reserved.
+            return ["Occupied", "Unavailable"].iter()
+                .find(|status| statuses.iter().any(|(_, evse_status)| evse_status == *status))
+                .copied()
+                .unwrap_or("Rejected");
+        },
+    };
+
+    if let Some(previous_reservation) = storage::get_reservation(id) {
+        storage::delete_reservation(id);
+
+        release(previous_reservation.evse_id - 1);
+    }
+
+    let reservation = Reservation {
+        id,
+        evse_id: evse_index + 1,
+        connector_type: connector_type.map(String::from),
+        id_token,
+        group_id_token,
+        expiry_date_time,
+    };
+
+    println!("EVSE {} is reserved for IdToken {} until {} (reservation {}).", reservation.evse_id, reservation.id_token["idToken"], expiry_date_time.to_rfc3339(), id);
+
+    storage::set_reservation(reservation);
+
+    for (connector_index, connector) in storage::get_evse(evse_index).map(|evse| evse.connectors).unwrap_or_default().iter().enumerate() {
+        if connector.status == "Available" && is_connector_reserved(evse_index, connector_index) {
+            connectors::queue_status_notification(evse_index, connector_index, "Reserved");
+        }
+    }
+
+    "Accepted"
+}
+
+/// Cancels a reservation on request of CSMS. Returns CancelReservationStatus.
+pub fn cancel(id: u64) -> &'static str {
+    let reservation = match storage::get_reservation(id) {
+        Some(res) => res,
+        None => return "Rejected",
+    };
+
+    storage::delete_reservation(id);
+
+    release(reservation.evse_id - 1);
+
+    println!("Reservation {} of EVSE {} was cancelled.", id, reservation.evse_id);
+
+    "Accepted"
+}
+
+/// Checks whether a driver may use an EVSE: it's not reserved, or it's reserved for the driver or their group.
+pub fn is_allowed(evse_index: usize, id_token: &JsonValue, group_id_token: &JsonValue) -> bool {
+    let reservation = match storage::get_evse_reservation(evse_index + 1) {
+        Some(res) => res,
+        None => return true,
+    };
+
+    authorization::is_same_token(&reservation.id_token, id_token)
+        || reservation.group_id_token.is_some_and(|reservation_group| group_id_token.is_object() && authorization::is_same_token(&reservation_group, group_id_token))
+}
+
+/// Ends the reservation of an EVSE once the driver is authorized there. Returns ID of the used reservation.
+pub fn use_reservation(evse_index: usize) -> Option<u64> {
+    let reservation = storage::get_evse_reservation(evse_index + 1)?;
+
+    storage::delete_reservation(reservation.id);
+
+    release(evse_index);
+
+    println!("Reservation {} of EVSE {} is used.", reservation.id, reservation.evse_id);
+
+    Some(reservation.id)
+}
+
+/// Removes reservations of EVSEs whose reserved connectors became inoperative.
+///
+/// Statuses of the connectors are left to the caller, which reports the change of availability.
+pub fn remove_inoperative() {
+    for reservation in storage::get_reservations() {
+        let evse_index = reservation.evse_id - 1;
+        let connectors = storage::get_evse(evse_index).map(|evse| evse.connectors).unwrap_or_default();
+
+        let operational = connectors.iter().enumerate()
+            .any(|(connector_index, connector)| reservation.covers(connector) && storage::is_connector_operational(evse_index, connector_index));
+
+        if !operational {
+            storage::delete_reservation(reservation.id);
+
+            println!("Reservation {} of EVSE {} was removed, the EVSE is inoperative.", reservation.id, reservation.evse_id);
+
+            queue_status_update(reservation.id, "Removed");
+        }
+    }
+}
+
+/// Removes reservations which expired.
+fn expire() {
+    let now = Utc::now();
+
+    for reservation in storage::get_reservations().into_iter().filter(|reservation| reservation.expiry_date_time <= now) {
+        storage::delete_reservation(reservation.id);
+
+        release(reservation.evse_id - 1);
+
+        println!("Reservation {} of EVSE {} expired.", reservation.id, reservation.evse_id);
+
+        queue_status_update(reservation.id, "Expired");
+    }
+}
+
+/// Starts checking whether reservations expired.
+///
+/// Reservations expire while the station is offline too, updates are queued until they can be sent.
+pub fn start() {
+    thread::spawn(|| loop {
+        thread::sleep(Duration::from_millis(EXPIRY_CHECK_INTERVAL));
+
+        expire();
+    });
+}
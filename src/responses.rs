@@ -1,14 +1,19 @@
 use json::stringify;
 use json::JsonValue;
 
-// OCPP constant.
+// OCPP constants.
 const CALLRESULT: u8 = 3;
+const CALLERROR: u8 = 4;
 
 /// Wrap a CALLRESULT message.
 fn wrap_call_result(msg_id: &str, payload: &str) -> String {
     format!("[{}, \"{}\", {}]", CALLRESULT, msg_id, payload)
 }
 
+pub fn call_error(msg_id: &str, error_code: &str, error_description: &str) -> String {
+    format!("[{}, {}, {}, {}, {{}}]", CALLERROR, stringify(msg_id), stringify(error_code), stringify(error_description))
+}
+
 pub fn set_variables(msg_id: &str, variables: JsonValue) -> String {
     let payload = object!{
         "setVariableResult" => variables,
@@ -25,12 +30,16 @@ pub fn get_variables(msg_id: &str, variables: JsonValue) -> String {
     wrap_call_result(msg_id, &stringify(payload))
 }
 
-pub fn request_start_transaction(msg_id: &str, remote_start_id: u64, status: &str) -> String {
-    let payload = object!{
+pub fn request_start_transaction(msg_id: &str, remote_start_id: u64, status: &str, transaction_id: Option<&str>) -> String {
+    let mut payload = object!{
         "remoteStartId" => remote_start_id,
         "status" => status,
     };
 
+    if let Some(data) = transaction_id {
+        payload["transactionId"] = data.into();
+    }
+
     wrap_call_result(msg_id, &stringify(payload))
 }
 
@@ -41,3 +50,111 @@ pub fn request_stop_transaction(msg_id: &str, status: &str) -> String {
 
     wrap_call_result(msg_id, &stringify(payload))
 }
+
+pub fn clear_cache(msg_id: &str, status: &str) -> String {
+    let payload = object!{
+        "status" => status,
+    };
+
+    wrap_call_result(msg_id, &stringify(payload))
+}
+
+pub fn send_local_list(msg_id: &str, status: &str) -> String {
+    let payload = object!{
+        "status" => status,
+    };
+
+    wrap_call_result(msg_id, &stringify(payload))
+}
+
+pub fn get_local_list_version(msg_id: &str, version_number: u64) -> String {
+    let payload = object!{
+        "versionNumber" => version_number,
+    };
+
+    wrap_call_result(msg_id, &stringify(payload))
+}
+
+pub fn reset(msg_id: &str, status: &str) -> String {
+    let payload = object!{
+        "status" => status,
+    };
+
+    wrap_call_result(msg_id, &stringify(payload))
+}
+
+pub fn change_availability(msg_id: &str, status: &str) -> String {
+    let payload = object!{
+        "status" => status,
+    };
+
+    wrap_call_result(msg_id, &stringify(payload))
+}
+
+pub fn unlock_connector(msg_id: &str, status: &str) -> String {
+    let payload = object!{
+        "status" => status,
+    };
+
+    wrap_call_result(msg_id, &stringify(payload))
+}
+
+pub fn trigger_message(msg_id: &str, status: &str) -> String {
+    let payload = object!{
+        "status" => status,
+    };
+
+    wrap_call_result(msg_id, &stringify(payload))
+}
+
+pub fn reserve_now(msg_id: &str, status: &str) -> String {
+    let payload = object!{
+        "status" => status,
+    };
+
+    wrap_call_result(msg_id, &stringify(payload))
+}
+
+pub fn cancel_reservation(msg_id: &str, status: &str) -> String {
+    let payload = object!{
+        "status" => status,
+    };
+
+    wrap_call_result(msg_id, &stringify(payload))
+}
+
+pub fn set_charging_profile(msg_id: &str, status: &str) -> String {
+    let payload = object!{
+        "status" => status,
+    };
+
+    wrap_call_result(msg_id, &stringify(payload))
+}
+
+pub fn get_charging_profiles(msg_id: &str, status: &str) -> String {
+    let payload = object!{
+        "status" => status,
+    };
+
+    wrap_call_result(msg_id, &stringify(payload))
+}
+
+pub fn clear_charging_profile(msg_id: &str, status: &str) -> String {
+    let payload = object!{
+        "status" => status,
+    };
+
+    wrap_call_result(msg_id, &stringify(payload))
+}
+
+pub fn get_composite_schedule(msg_id: &str, status: &str, schedule: Option<JsonValue>) -> String {
+    let mut payload = object!{
+        "status" => status,
+    };
+
+    if let Some(data) = schedule {
+        payload["schedule"] = data;
+    }
+
+    wrap_call_result(msg_id, &stringify(payload))
+}
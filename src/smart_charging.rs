@@ -0,0 +1,1160 @@
+use std::cmp::Reverse;
+
+use chrono::prelude::*;
+use json::JsonValue;
+use uuid::Uuid;
+
+use crate::components;
+use crate::meter;
+use crate::payload::{self, CallError};
+use crate::requests;
+use crate::storage;
+use crate::transactions;
+
+// Maximum number of installed charging profiles.
+pub const CHARGING_PROFILE_ENTRIES: usize = 100;
+// Values of ChargingProfilePurposeEnumType.
+pub const PURPOSES: [&str; 4] = ["ChargingStationExternalConstraints", "ChargingStationMaxProfile", "TxDefaultProfile", "TxProfile"];
+// Values of ChargingProfileKindEnumType.
+const KINDS: [&str; 3] = ["Absolute", "Recurring", "Relative"];
+// Values of ChargingLimitSourceEnumType.
+pub const LIMIT_SOURCES: [&str; 4] = ["EMS", "Other", "SO", "CSO"];
+// Purpose of limits set by external systems.
+const EXTERNAL_PURPOSE: &str = "ChargingStationExternalConstraints";
+// Length of recurring schedules, in seconds.
+const DAY: i64 = 24 * 60 * 60;
+const WEEK: i64 = 7 * DAY;
+
+// Period of a charging schedule.
+#[derive(Clone, Debug)]
+pub struct ChargingSchedulePeriod {
+    // Start of the period from the start of the schedule, in seconds.
+    pub start_period: u64,
+    pub limit: f64,
+    pub number_phases: Option<u64>,
+    pub phase_to_use: Option<u64>,
+}
+
+impl ChargingSchedulePeriod {
+    fn from_payload(payload: &JsonValue) -> Result<ChargingSchedulePeriod, CallError> {
+        Ok(ChargingSchedulePeriod {
+            start_period: payload::required_u64(payload, "startPeriod")?,
+            limit: payload::required_f64(payload, "limit")?,
+            number_phases: payload::optional_u64(payload, "numberPhases")?,
+            phase_to_use: payload::optional_u64(payload, "phaseToUse")?,
+        })
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut period = object!{
+            "startPeriod" => self.start_period,
+            "limit" => self.limit,
+        };
+
+        if let Some(data) = self.number_phases {
+            period["numberPhases"] = data.into();
+        }
+
+        if let Some(data) = self.phase_to_use {
+            period["phaseToUse"] = data.into();
+        }
+
+        period
+    }
+}
+
+// Charging schedule of a profile.
+#[derive(Clone, Debug)]
+pub struct ChargingSchedule {
+    pub id: Option<u64>,
+    pub start_schedule: Option<DateTime<Utc>>,
+    // Duration of the schedule, in seconds. The last period doesn't end if it's not given.
+    pub duration: Option<u64>,
+    // Unit of period limits, A or W.
+    pub charging_rate_unit: String,
+    pub periods: Vec<ChargingSchedulePeriod>,
+    pub min_charging_rate: Option<f64>,
+}
+
+impl ChargingSchedule {
+    fn from_payload(payload: &JsonValue) -> Result<ChargingSchedule, CallError> {
+        let periods: Result<Vec<_>, CallError> = payload::required_array(payload, "chargingSchedulePeriod")?.members()
+            .map(ChargingSchedulePeriod::from_payload)
+            .collect();
+
+        Ok(ChargingSchedule {
+            id: payload::optional_u64(payload, "id")?,
+            start_schedule: payload::optional_date_time(payload, "startSchedule")?,
+            duration: payload::optional_u64(payload, "duration")?,
+            charging_rate_unit: payload::required_enum(payload, "chargingRateUnit", &["A", "W"])?.to_string(),
+            periods: periods?,
+            min_charging_rate: payload::optional_f64(payload, "minChargingRate")?,
+        })
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut periods = JsonValue::new_array();
+
+        for period in self.periods.iter() {
+            periods.push(period.to_json()).unwrap();
+        }
+
+        let mut schedule = object!{
+            "chargingRateUnit" => self.charging_rate_unit.as_str(),
+            "chargingSchedulePeriod" => periods,
+        };
+
+        if let Some(data) = self.id {
+            schedule["id"] = data.into();
+        }
+
+        if let Some(data) = self.start_schedule {
+            schedule["startSchedule"] = data.to_rfc3339().into();
+        }
+
+        if let Some(data) = self.duration {
+            schedule["duration"] = data.into();
+        }
+
+        if let Some(data) = self.min_charging_rate {
+            schedule["minChargingRate"] = data.into();
+        }
+
+        schedule
+    }
+
+    /// Returns the period which is active at a moment of the schedule, in seconds from its start.
+    fn period_at(&self, elapsed: i64) -> Option<&ChargingSchedulePeriod> {
+        if elapsed < 0 || self.duration.is_some_and(|duration| elapsed >= duration as i64) {
+            return None;
+        }
+
+        self.periods.iter().rev().find(|period| period.start_period as i64 <= elapsed)
+    }
+}
+
+// Charging profile installed on an EVSE or the whole station.
+#[derive(Clone, Debug)]
+pub struct ChargingProfile {
+    pub id: u64,
+    // EVSE the profile is installed on, 0 for the whole station.
+    pub evse_id: usize,
+    pub stack_level: u64,
+    pub purpose: String,
+    pub kind: String,
+    pub recurrency_kind: Option<String>,
+    pub valid_from: Option<DateTime<Utc>>,
+    pub valid_to: Option<DateTime<Utc>>,
+    pub transaction_id: Option<String>,
+    pub schedules: Vec<ChargingSchedule>,
+    // Whether schedules were received as a list, as in OCPP 2.0.1, rather than a single schedule.
+    pub schedule_list: bool,
+    // Source of the limit: CSO for profiles set by CSMS, other sources for external limits.
+    pub source: String,
+}
+
+impl ChargingProfile {
+    /// Reads ChargingProfileType installed on an EVSE.
+    pub fn from_payload(evse_id: usize, payload: &JsonValue) -> Result<ChargingProfile, CallError> {
+        let schedule_list = payload["chargingSchedule"].is_array();
+
+        let schedules: Result<Vec<_>, CallError> = if schedule_list {
+            payload::required_array(payload, "chargingSchedule")?.members().map(ChargingSchedule::from_payload).collect()
+        } else {
+            ChargingSchedule::from_payload(payload::required_object(payload, "chargingSchedule")?).map(|schedule| vec![schedule])
+        };
+
+        Ok(ChargingProfile {
+            id: payload::required_u64(payload, "id")?,
+            evse_id,
+            stack_level: payload::required_u64(payload, "stackLevel")?,
+            purpose: payload::required_enum(payload, "chargingProfilePurpose", &PURPOSES)?.to_string(),
+            kind: payload::required_enum(payload, "chargingProfileKind", &KINDS)?.to_string(),
+            recurrency_kind: payload::optional_enum(payload, "recurrencyKind", &["Daily", "Weekly"])?.map(String::from),
+            valid_from: payload::optional_date_time(payload, "validFrom")?,
+            valid_to: payload::optional_date_time(payload, "validTo")?,
+            transaction_id: payload::optional_str(payload, "transactionId")?.map(String::from),
+            schedules: schedules?,
+            schedule_list,
+            source: "CSO".to_string(),
+        })
+    }
+
+    /// Restores a saved profile.
+    pub fn from_json(data: &JsonValue) -> Option<ChargingProfile> {
+        let mut profile = ChargingProfile::from_payload(data["evseId"].as_usize()?, &data["chargingProfile"]).ok()?;
+
+        profile.source = data["source"].as_str()?.to_string();
+
+        Some(profile)
+    }
+
+    /// Builds ChargingProfileType.
+    pub fn to_json(&self) -> JsonValue {
+        let mut schedules = JsonValue::new_array();
+
+        for schedule in self.schedules.iter() {
+            schedules.push(schedule.to_json()).unwrap();
+        }
+
+        let mut profile = object!{
+            "id" => self.id,
+            "stackLevel" => self.stack_level,
+            "chargingProfilePurpose" => self.purpose.as_str(),
+            "chargingProfileKind" => self.kind.as_str(),
+            "chargingSchedule" => if self.schedule_list { schedules } else { schedules[0].clone() },
+        };
+
+        if let Some(data) = &self.recurrency_kind {
+            profile["recurrencyKind"] = data.as_str().into();
+        }
+
+        if let Some(data) = self.valid_from {
+            profile["validFrom"] = data.to_rfc3339().into();
+        }
+
+        if let Some(data) = self.valid_to {
+            profile["validTo"] = data.to_rfc3339().into();
+        }
+
+        if let Some(data) = &self.transaction_id {
+            profile["transactionId"] = data.as_str().into();
+        }
+
+        profile
+    }
+
+    /// Returns when the schedule started last time before the given time, as a timestamp in seconds.
+    ///
+    /// Relative schedule starts with the transaction. Recurring schedule starts again every day or week.
+    fn schedule_start(&self, schedule: &ChargingSchedule, at: i64, transaction_start: i64) -> Option<i64> {
+        let start_schedule = schedule.start_schedule.map(|start_schedule| start_schedule.timestamp());
+
+        match self.kind.as_str() {
+            "Relative" => Some(transaction_start),
+            "Recurring" => {
+                let start_schedule = start_schedule?;
+                let recurrence = if self.recurrency_kind.as_deref() == Some("Weekly") { WEEK } else { DAY };
+
+                if at < start_schedule {
+                    return Some(start_schedule);
+                }
+
+                Some(start_schedule + (at - start_schedule) / recurrence * recurrence)
+            },
+            _ => start_schedule,
+        }
+    }
+
+    /// Returns the limit of the profile at the given time in W, if the profile is valid and its schedule
+    /// has an active period then. Current is converted for an EVSE, or the whole station if no EVSE is given.
+    fn limit_at(&self, at: DateTime<Utc>, transaction_start: DateTime<Utc>, evse_index: Option<usize>) -> Option<f64> {
+        if self.valid_from.is_some_and(|valid_from| at < valid_from) || self.valid_to.is_some_and(|valid_to| at >= valid_to) {
+            return None;
+        }
+
+        let schedule = self.schedules.first()?;
+        let start = self.schedule_start(schedule, at.timestamp(), transaction_start.timestamp())?;
+        let period = schedule.period_at(at.timestamp() - start)?;
+
+        if schedule.charging_rate_unit == "A" {
+            Some(meter::to_watts(evse_index, period.limit, period.number_phases))
+        } else {
+            Some(period.limit)
+        }
+    }
+
+    /// Returns the first time after the given one when the limit of the profile may change, as a timestamp in seconds:
+    /// the profile becomes valid or invalid, a period starts, the schedule ends or starts again.
+    fn next_change(&self, at: i64, transaction_start: i64) -> Option<i64> {
+        let mut changes: Vec<i64> = [self.valid_from, self.valid_to].iter().flatten().map(DateTime::timestamp).collect();
+
+        if let Some(schedule) = self.schedules.first() {
+            if let Some(start) = self.schedule_start(schedule, at, transaction_start) {
+                changes.extend(schedule.periods.iter().map(|period| start + period.start_period as i64));
+                changes.extend(schedule.duration.map(|duration| start + duration as i64));
+
+                if self.kind == "Recurring" {
+                    changes.push(start + if self.recurrency_kind.as_deref() == Some("Weekly") { WEEK } else { DAY });
+                }
+            }
+        }
+
+        changes.into_iter().filter(|change| *change > at).min()
+    }
+}
+
+// Limit set by an external system, such as a local EMS or grid operator, for an EVSE or the whole station.
+#[derive(Clone, Debug)]
+pub struct ExternalLimit {
+    // EVSE the limit is set for, 0 for the whole station.
+    pub evse_id: usize,
+    // Source of the limit, any ChargingLimitSourceEnumType but CSO.
+    pub source: String,
+    // Limit, in W.
+    pub limit: f64,
+    // When the limit was set.
+    pub set_at: DateTime<Utc>,
+    // ID of the schedule reported with NotifyChargingLimit.
+    pub schedule_id: u64,
+}
+
+impl ExternalLimit {
+    pub fn to_json(&self) -> JsonValue {
+        object!{
+            "evseId" => self.evse_id,
+            "source" => self.source.as_str(),
+            "limit" => self.limit,
+            "setAt" => self.set_at.to_rfc3339(),
+            "scheduleId" => self.schedule_id,
+        }
+    }
+
+    pub fn from_json(data: &JsonValue) -> Option<ExternalLimit> {
+        Some(ExternalLimit {
+            evse_id: data["evseId"].as_usize()?,
+            source: data["source"].as_str()?.to_string(),
+            limit: data["limit"].as_f64()?,
+            set_at: DateTime::parse_from_rfc3339(data["setAt"].as_str()?).ok()?.with_timezone(&Utc),
+            schedule_id: data["scheduleId"].as_u64().unwrap_or(1),
+        })
+    }
+
+    /// Builds the schedule of the limit, which starts when the limit was set and doesn't end.
+    fn schedule(&self) -> ChargingSchedule {
+        ChargingSchedule {
+            id: Some(self.schedule_id),
+            start_schedule: Some(self.set_at),
+            duration: None,
+            charging_rate_unit: "W".to_string(),
+            periods: vec![ChargingSchedulePeriod {
+                start_period: 0,
+                limit: self.limit,
+                number_phases: None,
+                phase_to_use: None,
+            }],
+            min_charging_rate: None,
+        }
+    }
+}
+
+// Criteria to select charging profiles. Criteria which are not given match any profile.
+#[derive(Clone, Debug, Default)]
+pub struct Criteria {
+    pub evse_id: Option<usize>,
+    pub purpose: Option<String>,
+    pub stack_level: Option<u64>,
+    pub ids: Vec<u64>,
+    pub sources: Vec<String>,
+}
+
+impl Criteria {
+    /// Reads ChargingProfileCriterionType of profiles installed on an EVSE.
+    pub fn from_payload(evse_id: Option<u64>, payload: &JsonValue) -> Result<Criteria, CallError> {
+        let ids: Option<Vec<u64>> = payload::optional_array(payload, "chargingProfileId")?.members().map(JsonValue::as_u64).collect();
+        let sources: Option<Vec<String>> = payload::optional_array(payload, "chargingLimitSource")?.members()
+            .map(|source| source.as_str().filter(|source| LIMIT_SOURCES.contains(source)).map(String::from))
+            .collect();
+
+        Ok(Criteria {
+            evse_id: evse_id.map(|evse_id| evse_id as usize),
+            purpose: payload::optional_enum(payload, "chargingProfilePurpose", &PURPOSES)?.map(String::from),
+            stack_level: payload::optional_u64(payload, "stackLevel")?,
+            ids: ids.ok_or_else(|| CallError::new("TypeConstraintViolation", "Field \"chargingProfileId\" must be a list of integers"))?,
+            sources: sources.ok_or_else(|| CallError::new("PropertyConstraintViolation", &format!("Field \"chargingLimitSource\" must be a list of {}", LIMIT_SOURCES.join(", "))))?,
+        })
+    }
+
+    fn matches(&self, profile: &ChargingProfile) -> bool {
+        self.evse_id.is_none_or(|evse_id| evse_id == profile.evse_id)
+            && self.purpose.as_ref().is_none_or(|purpose| *purpose == profile.purpose)
+            && self.stack_level.is_none_or(|stack_level| stack_level == profile.stack_level)
+            && (self.ids.is_empty() || self.ids.contains(&profile.id))
+            && (self.sources.is_empty() || self.sources.contains(&profile.source))
+    }
+}
+
+/// Returns number of installed charging profiles.
+pub fn profile_count() -> usize {
+    storage::get_charging_profiles().len()
+}
+
+/// Checks whether schedules of a profile match its kind and their periods follow in order. Returns the reason
+/// why they don't.
+fn check_schedules(profile: &ChargingProfile) -> Result<(), String> {
+    match profile.kind.as_str() {
+        "Relative" if profile.schedules.iter().any(|schedule| schedule.start_schedule.is_some()) => return Err("relative schedule can't have a start".to_string()),
+        "Absolute" | "Recurring" if profile.schedules.iter().any(|schedule| schedule.start_schedule.is_none()) => return Err("schedule has no start".to_string()),
+        "Recurring" if profile.recurrency_kind.is_none() => return Err("recurring profile has no recurrency kind".to_string()),
+        _ => (),
+    }
+
+    if profile.schedules.is_empty() {
+        return Err("profile has no schedule".to_string());
+    }
+
+    for schedule in profile.schedules.iter() {
+        if schedule.periods.is_empty() {
+            return Err("schedule has no periods".to_string());
+        }
+
+        if schedule.periods[0].start_period != 0 || schedule.periods.windows(2).any(|periods| periods[0].start_period >= periods[1].start_period) {
+            return Err("periods must start at 0 and follow in order".to_string());
+        }
+
+        if schedule.periods.iter().any(|period| period.limit < 0.0) {
+            return Err("limit can't be negative".to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks whether a profile fits among the installed ones: no other profile of the EVSE has the same purpose and
+/// stack level, and there is room for it unless it replaces a profile. Returns the reason why it doesn't fit.
+fn check_installed(profile: &ChargingProfile, installed_profiles: &[ChargingProfile]) -> Result<(), String> {
+    let conflict = installed_profiles.iter().any(|installed_profile| installed_profile.id != profile.id
+        && installed_profile.evse_id == profile.evse_id
+        && installed_profile.purpose == profile.purpose
+        && installed_profile.stack_level == profile.stack_level
+        && installed_profile.transaction_id == profile.transaction_id);
+
+    if conflict {
+        return Err(format!("another {} has stack level {}", profile.purpose, profile.stack_level));
+    }
+
+    if installed_profiles.len() >= CHARGING_PROFILE_ENTRIES && !installed_profiles.iter().any(|installed_profile| installed_profile.id == profile.id) {
+        return Err("there is no room for more profiles".to_string());
+    }
+
+    Ok(())
+}
+
+/// Checks whether a charging profile can be installed. Returns the reason why it can't.
+///
+/// TxProfile of a transaction which is about to start is installed once the transaction exists.
+fn validate(profile: &ChargingProfile, transaction_pending: bool) -> Result<(), String> {
+    let evse_count = storage::get_evses().len();
+
+    if !components::get_bool("SmartChargingCtrlr", "Enabled") || !components::get_bool("SmartChargingCtrlr", "Available") {
+        return Err("smart charging is disabled".to_string());
+    }
+
+    if profile.evse_id > evse_count {
+        return Err(format!("EVSE {} doesn't exist", profile.evse_id));
+    }
+
+    match profile.purpose.as_str() {
+        EXTERNAL_PURPOSE => return Err("external constraints can't be set by CSMS".to_string()),
+        "ChargingStationMaxProfile" if profile.evse_id != 0 => return Err("ChargingStationMaxProfile can be set only for EVSE 0".to_string()),
+        "TxProfile" if profile.evse_id == 0 => return Err("TxProfile can't be set for EVSE 0".to_string()),
+        "TxProfile" if !transaction_pending => {
+            let transaction_id = profile.transaction_id.as_deref().ok_or("TxProfile has no transaction ID")?;
+
+            if storage::get_transaction(transaction_id).is_none_or(|transaction| transaction.evse_id != profile.evse_id) {
+                return Err(format!("transaction {} is not ongoing at EVSE {}", transaction_id, profile.evse_id));
+            }
+        },
+        _ => (),
+    }
+
+    if profile.stack_level > components::get_integer("SmartChargingCtrlr", "ChargingProfileMaxStackLevel") {
+        return Err(format!("stack level {} is too high", profile.stack_level));
+    }
+
+    check_schedules(profile)?;
+
+    let rate_units = components::get_value("SmartChargingCtrlr", "ChargingScheduleChargingRateUnit");
+    let periods_per_schedule = components::get_integer("SmartChargingCtrlr", "PeriodsPerSchedule");
+
+    for schedule in profile.schedules.iter() {
+        if !rate_units.split(',').any(|rate_unit| rate_unit == schedule.charging_rate_unit) {
+            return Err(format!("charging rate unit {} is not supported", schedule.charging_rate_unit));
+        }
+
+        if schedule.periods.len() as u64 > periods_per_schedule {
+            return Err(format!("schedule has more than {} periods", periods_per_schedule));
+        }
+    }
+
+    check_installed(profile, &storage::get_charging_profiles())?;
+
+    Ok(())
+}
+
+/// Installs a charging profile set by CSMS. A profile with the ID of an installed one replaces it.
+/// Returns ChargingProfileStatus.
+pub fn set_profile(profile: ChargingProfile) -> &'static str {
+    if let Err(e) = validate(&profile, false) {
+        println!("Charging profile {} is rejected ({}).", profile.id, e);
+
+        return "Rejected";
+    }
+
+    println!("Charging profile {} ({}, stack level {}) is set for EVSE {}.", profile.id, profile.purpose, profile.stack_level, profile.evse_id);
+
+    storage::set_charging_profile(profile);
+
+    "Accepted"
+}
+
+/// Checks TxProfile sent with RequestStartTransaction for the transaction which is going to start at an EVSE.
+pub fn check_remote_start_profile(profile: &ChargingProfile, evse_index: usize) -> bool {
+    let profile = ChargingProfile {
+        evse_id: evse_index + 1,
+        ..profile.to_owned()
+    };
+
+    let result = if profile.purpose == "TxProfile" {
+        validate(&profile, true)
+    } else {
+        Err(format!("{} can't be sent with RequestStartTransaction", profile.purpose))
+    };
+
+    if let Err(e) = &result {
+        println!("Charging profile {} of remote start is rejected ({}).", profile.id, e);
+    }
+
+    result.is_ok()
+}
+
+/// Installs TxProfile sent with RequestStartTransaction for the transaction of an EVSE.
+pub fn set_remote_start_profile(profile: ChargingProfile, evse_index: usize) {
+    let transaction_id = match storage::get_evse_transaction(evse_index + 1) {
+        Some(transaction) => transaction.id,
+        None => {
+            println!("Charging profile {} of remote start isn't set, EVSE {} has no transaction.", profile.id, evse_index + 1);
+
+            return;
+        },
+    };
+
+    println!("Charging profile {} of remote start is set for transaction {}.", profile.id, transaction_id);
+
+    storage::set_charging_profile(ChargingProfile {
+        evse_id: evse_index + 1,
+        transaction_id: Some(transaction_id),
+        ..profile
+    });
+}
+
+/// Returns installed charging profiles which match the criteria.
+pub fn find_profiles(criteria: &Criteria) -> Vec<ChargingProfile> {
+    let mut profiles: Vec<ChargingProfile> = storage::get_charging_profiles().into_iter()
+        .filter(|profile| criteria.matches(profile))
+        .collect();
+
+    profiles.sort_by_key(|profile| (profile.evse_id, profile.source.to_owned(), profile.id));
+
+    profiles
+}
+
+/// Sends ReportChargingProfiles with the profiles of every EVSE and limit source.
+pub fn queue_reports(request_id: u64, profiles: Vec<ChargingProfile>) {
+    let mut reports: Vec<(usize, String, JsonValue)> = vec![];
+
+    for profile in profiles {
+        match reports.last_mut() {
+            Some((evse_id, source, charging_profiles)) if *evse_id == profile.evse_id && *source == profile.source => {
+                charging_profiles.push(profile.to_json()).unwrap();
+            },
+            _ => reports.push((profile.evse_id, profile.source.to_owned(), array![profile.to_json()])),
+        }
+    }
+
+    let report_count = reports.len();
+
+    for (i, (evse_id, source, charging_profiles)) in reports.into_iter().enumerate() {
+        let msg_id: &str = &Uuid::new_v4().to_string();
+
+        // More reports follow, except for the last one.
+        let tbc = i + 1 < report_count;
+
+        storage::queue_message(msg_id, requests::report_charging_profiles(msg_id, request_id, &source, tbc, evse_id, charging_profiles));
+    }
+}
+
+/// Removes charging profiles which match the criteria. Returns ClearChargingProfileStatus.
+pub fn clear_profiles(criteria: &Criteria) -> &'static str {
+    let profiles = find_profiles(criteria);
+
+    if profiles.is_empty() {
+        return "Unknown";
+    }
+
+    for profile in profiles {
+        storage::delete_charging_profile(profile.id);
+
+        println!("Charging profile {} of EVSE {} was cleared.", profile.id, profile.evse_id);
+    }
+
+    "Accepted"
+}
+
+/// Removes TxProfiles of a transaction once it's over.
+pub fn remove_transaction_profiles(transaction_id: &str) {
+    for profile in storage::get_charging_profiles() {
+        if profile.purpose == "TxProfile" && profile.transaction_id.as_deref() == Some(transaction_id) {
+            storage::delete_charging_profile(profile.id);
+        }
+    }
+}
+
+/// Returns the limit of the profile with the highest stack level which has an active period at the given time, in W.
+/// A profile of an EVSE takes precedence over a profile of the station with the same stack level.
+fn stack_limit(mut profiles: Vec<ChargingProfile>, at: DateTime<Utc>, transaction_start: DateTime<Utc>, evse_index: Option<usize>) -> Option<f64> {
+    profiles.sort_by_key(|profile| Reverse((profile.stack_level, profile.evse_id)));
+
+    profiles.iter().find_map(|profile| profile.limit_at(at, transaction_start, evse_index))
+}
+
+/// Returns ID of the transaction of an EVSE, if there is one, and when relative schedules of the EVSE start:
+/// with its transaction, or right away if it didn't start yet.
+fn evse_transaction(evse_index: Option<usize>) -> (Option<String>, DateTime<Utc>) {
+    let transaction = evse_index.and_then(|evse_index| storage::get_evse_transaction(evse_index + 1));
+
+    let transaction_start = transaction.as_ref()
+        .filter(|transaction| transaction.started)
+        .map(|transaction| Utc.timestamp((transaction.started_at / 1000) as i64, 0))
+        .unwrap_or_else(Utc::now);
+
+    (transaction.map(|transaction| transaction.id), transaction_start)
+}
+
+/// Merges charging profiles and external limits into the limit of an EVSE, or the whole station if no EVSE is given,
+/// at the given time in W.
+///
+/// TxProfile of the transaction takes precedence over TxDefaultProfile of the EVSE or the station.
+/// The limit is capped by external limits and ChargingStationMaxProfile.
+fn composite_limit(profiles: &[ChargingProfile], external_limits: &[ExternalLimit], evse_index: Option<usize>, transaction_id: Option<&str>, at: DateTime<Utc>, transaction_start: DateTime<Utc>) -> Option<f64> {
+    let select = |purpose: &str, evse_ids: &[usize]| -> Vec<ChargingProfile> {
+        profiles.iter()
+            .filter(|profile| profile.purpose == purpose && evse_ids.contains(&profile.evse_id))
+            .cloned()
+            .collect()
+    };
+
+    // The lowest limit of all sources applies.
+    let external_limit = |evse_id: usize| -> Option<f64> {
+        external_limits.iter()
+            .filter(|external_limit| external_limit.evse_id == evse_id)
+            .map(|external_limit| external_limit.limit)
+            .reduce(f64::min)
+    };
+
+    let mut limits = vec![
+        external_limit(0),
+        stack_limit(select("ChargingStationMaxProfile", &[0]), at, transaction_start, None),
+    ];
+
+    if let Some(evse_index) = evse_index {
+        let evse_id = evse_index + 1;
+
+        let tx_profiles: Vec<ChargingProfile> = select("TxProfile", &[evse_id]).into_iter()
+            .filter(|profile| transaction_id.is_some() && profile.transaction_id.as_deref() == transaction_id)
+            .collect();
+
+        let tx_limit = stack_limit(tx_profiles, at, transaction_start, Some(evse_index))
+            .or_else(|| stack_limit(select("TxDefaultProfile", &[0, evse_id]), at, transaction_start, Some(evse_index)));
+
+        limits.push(tx_limit);
+        limits.push(external_limit(evse_id));
+    }
+
+    limits.iter().flatten().copied().reduce(f64::min)
+}
+
+/// Returns the limit charging profiles and external limits set for an EVSE at the given time, in W, if there is one.
+pub fn evse_limit(evse_index: usize, at: DateTime<Utc>) -> Option<f64> {
+    let (transaction_id, transaction_start) = evse_transaction(Some(evse_index));
+
+    composite_limit(&storage::get_charging_profiles(), &storage::get_external_limits(), Some(evse_index), transaction_id.as_deref(), at, transaction_start)
+}
+
+/// Returns the limit charging profiles and external limits set for the whole station at the given time, in W,
+/// if there is one.
+pub fn station_limit(at: DateTime<Utc>) -> Option<f64> {
+    composite_limit(&storage::get_charging_profiles(), &storage::get_external_limits(), None, None, at, Utc::now())
+}
+
+/// Calculates the composite schedule of an EVSE, or the whole station for EVSE 0, from now for the given duration
+/// in seconds. Returns CompositeScheduleType, or the reason why it can't be calculated.
+///
+/// The schedule has a period for every change of the limit. Time without any limit is limited by the maximum
+/// power of the EVSE or the station. Limits are converted to the requested unit with supply phases of the EVSE.
+pub fn composite_schedule(evse_id: usize, duration: u64, charging_rate_unit: Option<&str>) -> Result<JsonValue, String> {
+    if !components::get_bool("SmartChargingCtrlr", "Enabled") || !components::get_bool("SmartChargingCtrlr", "Available") {
+        return Err("smart charging is disabled".to_string());
+    }
+
+    if evse_id > storage::get_evses().len() {
+        return Err(format!("EVSE {} doesn't exist", evse_id));
+    }
+
+    let rate_units = components::get_value("SmartChargingCtrlr", "ChargingScheduleChargingRateUnit");
+
+    // Without a requested unit, the first supported one is used.
+    let charging_rate_unit = match charging_rate_unit {
+        Some(res) if rate_units.split(',').any(|rate_unit| rate_unit == res) => res.to_string(),
+        Some(res) => return Err(format!("charging rate unit {} is not supported", res)),
+        None => rate_units.split(',').next().unwrap_or("W").to_string(),
+    };
+
+    let evse_index = evse_id.checked_sub(1);
+    let profiles = storage::get_charging_profiles();
+    let external_limits = storage::get_external_limits();
+    let (transaction_id, transaction_start) = evse_transaction(evse_index);
+    let max_power = meter::max_power(evse_index);
+
+    let schedule_start = match Utc::now().with_nanosecond(0) {
+        Some(res) => res,
+        None => panic!("Current date is empty."),
+    };
+
+    let start = schedule_start.timestamp();
+    let end = start + duration as i64;
+
+    let mut periods = JsonValue::new_array();
+    let mut previous_limit: Option<f64> = None;
+    let mut at = start;
+
+    while at < end {
+        let limit = composite_limit(&profiles, &external_limits, evse_index, transaction_id.as_deref(), Utc.timestamp(at, 0), transaction_start).unwrap_or(max_power).min(max_power);
+
+        if previous_limit != Some(limit) {
+            let mut period = object!{
+                "startPeriod" => at - start,
+                "limit" => meter::round(if charging_rate_unit == "A" { meter::to_amps(evse_index, limit) } else { limit }),
+            };
+
+            let supply_phases = meter::supply_phases(evse_index);
+
+            if supply_phases > 0 {
+                period["numberPhases"] = supply_phases.into();
+            }
+
+            periods.push(period).unwrap();
+
+            previous_limit = Some(limit);
+        }
+
+        at = profiles.iter()
+            .filter_map(|profile| profile.next_change(at, transaction_start.timestamp()))
+            .min()
+            .unwrap_or(end);
+    }
+
+    Ok(object!{
+        "evseId" => evse_id,
+        "duration" => duration,
+        "scheduleStart" => schedule_start.to_rfc3339(),
+        "chargingRateUnit" => charging_rate_unit,
+        "chargingSchedulePeriod" => periods,
+    })
+}
+
+/// Returns the current limits of all EVSEs, to find out which of them change.
+fn evse_limits() -> Vec<Option<f64>> {
+    (0..storage::get_evses().len()).map(|evse_index| evse_limit(evse_index, Utc::now())).collect()
+}
+
+/// Checks whether a limit changed by more than SmartChargingCtrlr.LimitChangeSignificance percent. A limit which
+/// appears or disappears always changes significantly.
+pub fn is_significant_change(previous_limit: Option<f64>, limit: Option<f64>) -> bool {
+    let significance: f64 = components::get_value("SmartChargingCtrlr", "LimitChangeSignificance").parse().unwrap_or(0.0);
+
+    match (previous_limit, limit) {
+        (Some(previous_limit), Some(limit)) => (limit - previous_limit).abs() > previous_limit * significance / 100.0,
+        (previous_limit, limit) => previous_limit.is_some() != limit.is_some(),
+    }
+}
+
+/// Reports significant changes of EVSE limits to transactions with ChargingRateChanged.
+fn report_rate_changes(previous_limits: Vec<Option<f64>>) {
+    for (evse_index, previous_limit) in previous_limits.into_iter().enumerate() {
+        let significant = is_significant_change(previous_limit, evse_limit(evse_index, Utc::now()));
+
+        if significant {
+            transactions::report_meter_values(evse_index, "ChargingRateChanged", JsonValue::new_array());
+        }
+    }
+}
+
+/// Sets the limit of an external system, such as a local EMS or grid operator, for an EVSE or the whole station
+/// for EVSE 0, and reports it with NotifyChargingLimit. The limit replaces a previous one of the same source.
+///
+/// External limits are kept apart from charging profiles set by CSMS, and cap the limit of charging profiles.
+pub fn set_external_limit(evse_id: usize, limit: f64, source: &str) -> Result<(), String> {
+    if !components::get_bool("SmartChargingCtrlr", "ExternalControlSignalsEnabled") {
+        return Err("external control signals are disabled".to_string());
+    }
+
+    if evse_id > storage::get_evses().len() {
+        return Err(format!("EVSE {} doesn't exist", evse_id));
+    }
+
+    if !LIMIT_SOURCES.contains(&source) || source == "CSO" {
+        return Err(format!("limit source must be one of {}", LIMIT_SOURCES.iter().filter(|source| **source != "CSO").copied().collect::<Vec<&str>>().join(", ")));
+    }
+
+    if limit < 0.0 {
+        return Err("limit can't be negative".to_string());
+    }
+
+    let external_limits = storage::get_external_limits();
+    let previous_limit = external_limits.iter().find(|external_limit| external_limit.evse_id == evse_id && external_limit.source == source);
+
+    // A new limit gets the next free schedule ID.
+    let schedule_id = match previous_limit {
+        Some(external_limit) => external_limit.schedule_id,
+        None => external_limits.iter().map(|external_limit| external_limit.schedule_id + 1).max().unwrap_or(1),
+    };
+
+    let previous_limit = previous_limit.map(|external_limit| external_limit.limit);
+
+    let external_limit = ExternalLimit {
+        evse_id,
+        source: source.to_string(),
+        limit,
+        set_at: Utc::now().with_nanosecond(0).unwrap_or_else(Utc::now),
+        schedule_id,
+    };
+
+    let previous_limits = evse_limits();
+    let charging_schedules = array![external_limit.schedule().to_json()];
+
+    storage::set_external_limit(external_limit);
+
+    println!("External limit of {} W from {} is set for EVSE {}.", limit, source, evse_id);
+
+    if is_significant_change(previous_limit, Some(limit)) {
+        let msg_id: &str = &Uuid::new_v4().to_string();
+        let charging_schedules = Some(charging_schedules).filter(|_| components::get_bool("SmartChargingCtrlr", "NotifyChargingLimitWithSchedules"));
+
+        // Only the grid operator's limits are critical for the grid.
+        storage::queue_message(msg_id, requests::notify_charging_limit(msg_id, source, source == "SO", evse_id, charging_schedules));
+    }
+
+    report_rate_changes(previous_limits);
+
+    Ok(())
+}
+
+/// Releases external limits of an EVSE, or the whole station for EVSE 0, and reports them with ClearedChargingLimit.
+/// Limits of all sources are released if no source is given.
+pub fn clear_external_limit(evse_id: usize, source: Option<&str>) -> Result<(), String> {
+    let external_limits: Vec<ExternalLimit> = storage::get_external_limits().into_iter()
+        .filter(|external_limit| external_limit.evse_id == evse_id && source.is_none_or(|source| external_limit.source == source))
+        .collect();
+
+    if external_limits.is_empty() {
+        return Err(format!("EVSE {} has no external limit", evse_id));
+    }
+
+    let previous_limits = evse_limits();
+
+    for external_limit in external_limits {
+        storage::delete_external_limit(evse_id, &external_limit.source);
+
+        println!("External limit from {} is released for EVSE {}.", external_limit.source, evse_id);
+
+        let msg_id: &str = &Uuid::new_v4().to_string();
+
+        storage::queue_message(msg_id, requests::cleared_charging_limit(msg_id, &external_limit.source, evse_id));
+    }
+
+    report_rate_changes(previous_limits);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn schedules_have_to_match_kind() {
+        let mut profile = ChargingProfile::from_payload(1, &object!{
+            "id" => 1,
+            "stackLevel" => 0,
+            "chargingProfilePurpose" => "TxDefaultProfile",
+            "chargingProfileKind" => "Absolute",
+            "chargingSchedule" => object!{
+                "startSchedule" => "2024-01-01T00:00:00Z",
+                "chargingRateUnit" => "W",
+                "chargingSchedulePeriod" => array![object!{ "startPeriod" => 0, "limit" => 11000 }, object!{ "startPeriod" => 60, "limit" => 0 }],
+            },
+        }).unwrap();
+
+        assert!(check_schedules(&profile).is_ok());
+
+        profile.kind = "Relative".to_string();
+        assert!(check_schedules(&profile).is_err());
+
+        profile.schedules[0].start_schedule = None;
+        assert!(check_schedules(&profile).is_ok());
+
+        profile.kind = "Recurring".to_string();
+        assert!(check_schedules(&profile).is_err());
+    }
+
+    #[test]
+    fn periods_have_to_follow_in_order() {
+        let mut profile = ChargingProfile::from_payload(1, &object!{
+            "id" => 1,
+            "stackLevel" => 0,
+            "chargingProfilePurpose" => "TxDefaultProfile",
+            "chargingProfileKind" => "Relative",
+            "chargingSchedule" => object!{
+                "chargingRateUnit" => "W",
+                "chargingSchedulePeriod" => array![object!{ "startPeriod" => 0, "limit" => 11000 }, object!{ "startPeriod" => 60, "limit" => 0 }],
+            },
+        }).unwrap();
+
+        profile.schedules[0].periods[1].start_period = 0;
+        assert!(check_schedules(&profile).is_err());
+
+        profile.schedules[0].periods.remove(0);
+        profile.schedules[0].periods[0].start_period = 60;
+        assert!(check_schedules(&profile).is_err());
+
+        profile.schedules[0].periods[0].start_period = 0;
+        profile.schedules[0].periods[0].limit = -1.0;
+        assert!(check_schedules(&profile).is_err());
+
+        profile.schedules[0].periods.clear();
+        assert!(check_schedules(&profile).is_err());
+    }
+
+    #[test]
+    fn profile_has_to_fit_among_installed_profiles() {
+        let payload = object!{
+            "id" => 1,
+            "stackLevel" => 0,
+            "chargingProfilePurpose" => "TxDefaultProfile",
+            "chargingProfileKind" => "Relative",
+            "chargingSchedule" => object!{
+                "chargingRateUnit" => "W",
+                "chargingSchedulePeriod" => array![object!{ "startPeriod" => 0, "limit" => 11000 }],
+            },
+        };
+
+        let installed_profiles = vec![ChargingProfile::from_payload(1, &payload).unwrap()];
+        let mut profile = installed_profiles[0].clone();
+
+        // The same ID replaces the profile, another ID conflicts with it on the same EVSE and stack level.
+        assert!(check_installed(&profile, &installed_profiles).is_ok());
+
+        profile.id = 2;
+        assert!(check_installed(&profile, &installed_profiles).is_err());
+
+        profile.evse_id = 2;
+        assert!(check_installed(&profile, &installed_profiles).is_ok());
+
+        profile.evse_id = 1;
+        profile.stack_level = 1;
+        assert!(check_installed(&profile, &installed_profiles).is_ok());
+
+        // There's no room for another profile, but a profile can still be replaced.
+        let full_profiles: Vec<ChargingProfile> = (1..=CHARGING_PROFILE_ENTRIES as u64)
+            .map(|id| ChargingProfile { id, stack_level: id, ..installed_profiles[0].clone() })
+            .collect();
+
+        profile.id = 1000;
+        assert!(check_installed(&profile, &full_profiles).is_err());
+
+        profile.id = 1;
+        assert!(check_installed(&profile, &full_profiles).is_ok());
+    }
+
+    #[test]
+    fn composite_limit_uses_highest_stack_level() {
+        let at = time("2024-01-01T12:00:00Z");
+        let station_default = ChargingProfile::from_payload(0, &object!{
+            "id" => 1,
+            "stackLevel" => 0,
+            "chargingProfilePurpose" => "TxDefaultProfile",
+            "chargingProfileKind" => "Relative",
+            "chargingSchedule" => object!{
+                "chargingRateUnit" => "W",
+                "chargingSchedulePeriod" => array![object!{ "startPeriod" => 0, "limit" => 11000 }],
+            },
+        }).unwrap();
+
+        let evse_default = ChargingProfile { id: 2, evse_id: 1, ..station_default.clone() };
+        let mut higher_default = ChargingProfile { id: 3, stack_level: 1, ..station_default.clone() };
+        let mut profiles = vec![station_default, evse_default];
+
+        profiles[1].schedules[0].periods[0].limit = 7000.0;
+        higher_default.schedules[0].periods[0].limit = 9000.0;
+
+        // A profile of the EVSE takes precedence over a profile of the station with the same stack level.
+        assert_eq!(composite_limit(&profiles, &[], Some(0), None, at, at), Some(7000.0));
+        assert_eq!(composite_limit(&profiles, &[], Some(1), None, at, at), Some(11000.0));
+        assert_eq!(composite_limit(&profiles, &[], None, None, at, at), None);
+
+        profiles.push(higher_default);
+
+        assert_eq!(composite_limit(&profiles, &[], Some(0), None, at, at), Some(9000.0));
+    }
+
+    #[test]
+    fn tx_profile_applies_to_its_transaction() {
+        let at = time("2024-01-01T12:00:00Z");
+        let tx_default = ChargingProfile::from_payload(1, &object!{
+            "id" => 1,
+            "stackLevel" => 1,
+            "chargingProfilePurpose" => "TxDefaultProfile",
+            "chargingProfileKind" => "Relative",
+            "chargingSchedule" => object!{
+                "chargingRateUnit" => "W",
+                "chargingSchedulePeriod" => array![object!{ "startPeriod" => 0, "limit" => 11000 }],
+            },
+        }).unwrap();
+
+        let mut tx_profile = ChargingProfile { id: 2, stack_level: 0, purpose: "TxProfile".to_string(), transaction_id: Some("A".to_string()), ..tx_default.clone() };
+
+        tx_profile.schedules[0].periods[0].limit = 3000.0;
+
+        let profiles = vec![tx_default, tx_profile];
+
+        assert_eq!(composite_limit(&profiles, &[], Some(0), Some("A"), at, at), Some(3000.0));
+        assert_eq!(composite_limit(&profiles, &[], Some(0), Some("B"), at, at), Some(11000.0));
+        assert_eq!(composite_limit(&profiles, &[], Some(0), None, at, at), Some(11000.0));
+    }
+
+    #[test]
+    fn relative_schedule_starts_with_transaction() {
+        let profile = ChargingProfile::from_payload(1, &object!{
+            "id" => 1,
+            "stackLevel" => 0,
+            "chargingProfilePurpose" => "TxDefaultProfile",
+            "chargingProfileKind" => "Relative",
+            "chargingSchedule" => object!{
+                "duration" => 3600,
+                "chargingRateUnit" => "W",
+                "chargingSchedulePeriod" => array![object!{ "startPeriod" => 0, "limit" => 5000 }, object!{ "startPeriod" => 600, "limit" => 8000 }],
+            },
+        }).unwrap();
+
+        let start = time("2024-01-01T12:00:00Z");
+        let limit_at = |at: &str| profile.limit_at(time(at), start, Some(0));
+
+        assert_eq!(limit_at("2024-01-01T12:09:59Z"), Some(5000.0));
+        assert_eq!(limit_at("2024-01-01T12:10:00Z"), Some(8000.0));
+        assert_eq!(limit_at("2024-01-01T13:00:00Z"), None);
+
+        assert_eq!(profile.next_change(time("2024-01-01T12:05:00Z").timestamp(), start.timestamp()), Some(time("2024-01-01T12:10:00Z").timestamp()));
+        assert_eq!(profile.next_change(time("2024-01-01T12:30:00Z").timestamp(), start.timestamp()), Some(time("2024-01-01T13:00:00Z").timestamp()));
+    }
+
+    #[test]
+    fn recurring_schedule_starts_again() {
+        let mut profile = ChargingProfile::from_payload(1, &object!{
+            "id" => 1,
+            "stackLevel" => 0,
+            "chargingProfilePurpose" => "TxDefaultProfile",
+            "chargingProfileKind" => "Recurring",
+            "recurrencyKind" => "Daily",
+            "chargingSchedule" => object!{
+                "startSchedule" => "2024-01-01T00:00:00Z",
+                "duration" => 12 * 3600,
+                "chargingRateUnit" => "W",
+                "chargingSchedulePeriod" => array![object!{ "startPeriod" => 0, "limit" => 10000 }, object!{ "startPeriod" => 8 * 3600, "limit" => 20000 }],
+            },
+        }).unwrap();
+
+        let limit_at = |profile: &ChargingProfile, at: &str| profile.limit_at(time(at), time(at), Some(0));
+
+        assert_eq!(limit_at(&profile, "2023-12-31T12:00:00Z"), None);
+        assert_eq!(limit_at(&profile, "2024-01-05T07:59:59Z"), Some(10000.0));
+        assert_eq!(limit_at(&profile, "2024-01-05T08:00:00Z"), Some(20000.0));
+        assert_eq!(limit_at(&profile, "2024-01-05T12:00:00Z"), None);
+
+        assert_eq!(profile.next_change(time("2024-01-05T07:00:00Z").timestamp(), 0), Some(time("2024-01-05T08:00:00Z").timestamp()));
+        assert_eq!(profile.next_change(time("2024-01-05T13:00:00Z").timestamp(), 0), Some(time("2024-01-06T00:00:00Z").timestamp()));
+
+        // 2024-01-01 is a Monday.
+        profile.recurrency_kind = Some("Weekly".to_string());
+
+        assert_eq!(limit_at(&profile, "2024-01-05T09:00:00Z"), None);
+        assert_eq!(limit_at(&profile, "2024-01-08T09:00:00Z"), Some(20000.0));
+    }
+
+    #[test]
+    fn profile_is_limited_to_validity() {
+        let at = time("2024-01-01T00:00:00Z");
+        let lower_profile = ChargingProfile::from_payload(1, &object!{
+            "id" => 1,
+            "stackLevel" => 0,
+            "chargingProfilePurpose" => "TxDefaultProfile",
+            "chargingProfileKind" => "Relative",
+            "chargingSchedule" => object!{
+                "chargingRateUnit" => "W",
+                "chargingSchedulePeriod" => array![object!{ "startPeriod" => 0, "limit" => 5000 }],
+            },
+        }).unwrap();
+
+        let mut valid_profile = ChargingProfile {
+            id: 2,
+            stack_level: 1,
+            valid_from: Some(time("2024-01-01T10:00:00Z")),
+            valid_to: Some(time("2024-01-01T12:00:00Z")),
+            ..lower_profile.clone()
+        };
+
+        valid_profile.schedules[0].periods[0].limit = 11000.0;
+
+        // The profile with a lower stack level applies outside of the validity of the other one.
+        let profiles = vec![lower_profile, valid_profile.clone()];
+        let limit_at = |at: &str| composite_limit(&profiles, &[], Some(0), None, time(at), time(at));
+
+        assert_eq!(limit_at("2024-01-01T09:59:59Z"), Some(5000.0));
+        assert_eq!(limit_at("2024-01-01T10:00:00Z"), Some(11000.0));
+        assert_eq!(limit_at("2024-01-01T12:00:00Z"), Some(5000.0));
+
+        assert_eq!(valid_profile.next_change(time("2024-01-01T09:00:00Z").timestamp(), at.timestamp()), Some(time("2024-01-01T10:00:00Z").timestamp()));
+        assert_eq!(valid_profile.next_change(time("2024-01-01T11:00:00Z").timestamp(), at.timestamp()), Some(time("2024-01-01T12:00:00Z").timestamp()));
+    }
+
+    #[test]
+    fn composite_limit_is_capped_by_station_limits() {
+        let at = time("2024-01-01T12:00:00Z");
+        let tx_default = ChargingProfile::from_payload(2, &object!{
+            "id" => 1,
+            "stackLevel" => 0,
+            "chargingProfilePurpose" => "TxDefaultProfile",
+            "chargingProfileKind" => "Relative",
+            "chargingSchedule" => object!{
+                "chargingRateUnit" => "W",
+                "chargingSchedulePeriod" => array![object!{ "startPeriod" => 0, "limit" => 11000 }],
+            },
+        }).unwrap();
+
+        let mut station_max = ChargingProfile { id: 2, evse_id: 0, purpose: "ChargingStationMaxProfile".to_string(), ..tx_default.clone() };
+
+        station_max.schedules[0].periods[0].limit = 8000.0;
+
+        let profiles = vec![tx_default, station_max];
+
+        assert_eq!(composite_limit(&profiles, &[], Some(1), None, at, at), Some(8000.0));
+        assert_eq!(composite_limit(&profiles, &[], Some(0), None, at, at), Some(8000.0));
+        assert_eq!(composite_limit(&profiles, &[], None, None, at, at), Some(8000.0));
+
+        // The lowest limit of all sources applies.
+        let external_limits = vec![
+            ExternalLimit { evse_id: 1, source: "EMS".to_string(), limit: 6000.0, set_at: at, schedule_id: 1 },
+            ExternalLimit { evse_id: 1, source: "SO".to_string(), limit: 7000.0, set_at: at, schedule_id: 2 },
+            ExternalLimit { evse_id: 0, source: "SO".to_string(), limit: 7500.0, set_at: at, schedule_id: 3 },
+        ];
+
+        assert_eq!(composite_limit(&profiles, &external_limits, Some(0), None, at, at), Some(6000.0));
+        assert_eq!(composite_limit(&profiles, &external_limits, Some(1), None, at, at), Some(7500.0));
+        assert_eq!(composite_limit(&profiles, &external_limits, None, None, at, at), Some(7500.0));
+        assert_eq!(composite_limit(&[], &external_limits, None, None, at, at), Some(7500.0));
+    }
+}
@@ -1,37 +1,371 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Mutex;
 
-use queues::*;
+use chrono::prelude::*;
+use json::JsonValue;
+
+use crate::authorization::CacheEntry;
+use crate::ev::Ev;
+use crate::persistence;
+use crate::reservations::Reservation;
+use crate::smart_charging::{ChargingProfile, ExternalLimit};
+use crate::transactions::Transaction;
 
 // Connector struct.
 #[derive(Clone, Debug)]
 pub struct Connector {
+    pub connector_type: String,
     pub status: &'static str,
     pub operational: bool,
+    // Whether the cable is locked in the connector.
+    pub locked: bool,
+    // Simulated failure of the cable lock, which can be neither locked nor unlocked.
+    pub lock_failure: bool,
+}
+
+// Simulated energy meter of an EVSE.
+#[derive(Clone, Debug, Default)]
+pub struct Meter {
+    // Imported energy register, in Wh.
+    pub energy: f64,
+    // Active power, in W.
+    pub power: f64,
+    // Maximum power offered to the EV, in W.
+    pub power_offered: f64,
+    // Voltage, in V.
+    pub voltage: f64,
+    // Current per phase, in A.
+    pub current: f64,
+    // State of charge of the connected EV, in percent.
+    pub soc: Option<f64>,
+}
+
+// EVSE struct.
+#[derive(Clone, Debug)]
+pub struct Evse {
+    pub connectors: Vec<Connector>,
+    pub operational: bool,
+    pub meter: Meter,
+    // EV connected to the EVSE.
+    pub ev: Option<Ev>,
 }
 
-// Basic information about sent message.
+// Basic information about sent message which awaits a response.
 #[derive(Clone, Debug)]
 pub struct SentMessage {
-    pub id: Option<String>,
-    pub timestamp: Option<u64>,
+    pub id: String,
+    pub action: String,
+    // When the message was sent last time, in milliseconds.
+    pub timestamp: u64,
+    // How many times the message was sent.
+    pub attempts: u64,
+    // When the message has to be sent again, in milliseconds. Set while waiting for a retry.
+    pub retry_at: Option<u64>,
 }
 
+// Interval in which changes of the state are saved, in seconds. Changes of the message queue and transactions
+// which start or end are saved right away.
+const PERSIST_INTERVAL: u64 = 10;
+
 lazy_static! {
-    // Array of EVSE each item of which contains an array of connectors.
-    static ref EVSES: Mutex<[[Connector; 1]; 1]> = Mutex::new([[Connector { status: "Inoperative", operational: true }]]);
+    // List of EVSE each item of which contains a list of connectors.
+    static ref EVSES: Mutex<Vec<Evse>> = Mutex::new(vec![]);
     // Sent OCPP messages hash map: message id => stringified message.
     static ref MESSAGES: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
-    // Saved transactions. transaction id => stringified transaction.
-    static ref TRANSACTIONS: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+    // Transactions, including the ones which are not started yet. transaction id => transaction.
+    static ref TRANSACTIONS: Mutex<HashMap<String, Transaction>> = Mutex::new(HashMap::new());
+    // Authorization cache. idToken type and value => entry.
+    static ref AUTH_CACHE: Mutex<HashMap<String, CacheEntry>> = Mutex::new(HashMap::new());
+    // Version of the local authorization list, 0 if there is no list.
+    static ref LOCAL_LIST_VERSION: Mutex<u64> = Mutex::new(0);
+    // Local authorization list. idToken type and value => IdTokenInfo.
+    static ref LOCAL_LIST: Mutex<HashMap<String, JsonValue>> = Mutex::new(HashMap::new());
     // Pending messages queue.
-    static ref QUEUE: Mutex<Queue<String>> = Mutex::new(queue![]);
-    // Last sent message.
-    static ref LAST_SENT_MESSAGE: Mutex<SentMessage> = Mutex::new(SentMessage { id: None, timestamp: None });
+    static ref QUEUE: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+    // Reservations of EVSEs by ID.
+    static ref RESERVATIONS: Mutex<HashMap<u64, Reservation>> = Mutex::new(HashMap::new());
+    // Charging profiles by ID.
+    static ref CHARGING_PROFILES: Mutex<HashMap<u64, ChargingProfile>> = Mutex::new(HashMap::new());
+    // Limits set by external systems. EVSE ID and limit source => limit.
+    static ref EXTERNAL_LIMITS: Mutex<HashMap<(usize, String), ExternalLimit>> = Mutex::new(HashMap::new());
+    // Last sent message which awaits a response.
+    static ref LAST_SENT_MESSAGE: Mutex<Option<SentMessage>> = Mutex::new(None);
+    // Registration status from the last BootNotification response: Accepted, Pending or Rejected.
+    // Empty until CSMS responds.
+    static ref REGISTRATION_STATUS: Mutex<&'static str> = Mutex::new("");
+    // ID of the last event reported with NotifyEvent.
+    static ref EVENT_ID: Mutex<u64> = Mutex::new(0);
+    // Operational status of the station as a whole.
+    static ref STATION_OPERATIONAL: Mutex<bool> = Mutex::new(true);
+    // Number of WebSocket connections opened so far.
+    static ref CONNECTIONS: Mutex<u64> = Mutex::new(0);
+    // Whether the WebSocket connection is open.
+    static ref CONNECTED: Mutex<bool> = Mutex::new(false);
+    // When the connection was lost last time, in seconds.
+    static ref DISCONNECTED_AT: Mutex<Option<u64>> = Mutex::new(None);
+    // Reason of the next BootNotification.
+    static ref BOOT_REASON: Mutex<&'static str> = Mutex::new("PowerUp");
+    // Resets which wait until there are no transactions: EVSE index, or None for the whole station.
+    static ref SCHEDULED_RESETS: Mutex<Vec<Option<usize>>> = Mutex::new(vec![]);
+    // When the state was changed without saving it for the first time, in seconds.
+    static ref UNSAVED_SINCE: Mutex<Option<u64>> = Mutex::new(None);
+}
+
+/// Builds a snapshot of the state which has to survive a restart.
+fn snapshot() -> JsonValue {
+    let mut evses = JsonValue::new_array();
+
+    for evse in EVSES.lock().unwrap().iter() {
+        let mut connectors = JsonValue::new_array();
+
+        for connector in evse.connectors.iter() {
+            connectors.push(object!{
+                "status" => connector.status,
+                "operational" => connector.operational,
+                "locked" => connector.locked,
+            }).unwrap();
+        }
+
+        evses.push(object!{
+            "connectors" => connectors,
+            "operational" => evse.operational,
+            "energy" => evse.meter.energy,
+            "ev" => evse.ev.as_ref().map(Ev::to_json),
+        }).unwrap();
+    }
+
+    let mut messages = JsonValue::new_object();
+
+    for (key, value) in MESSAGES.lock().unwrap().iter() {
+        messages[key.as_str()] = value.as_str().into();
+    }
+
+    let mut transactions = JsonValue::new_object();
+
+    for (key, value) in TRANSACTIONS.lock().unwrap().iter() {
+        transactions[key.as_str()] = value.to_json();
+    }
+
+    let mut auth_cache = JsonValue::new_object();
+
+    for (key, value) in AUTH_CACHE.lock().unwrap().iter() {
+        auth_cache[key.as_str()] = value.to_json();
+    }
+
+    let mut local_list = JsonValue::new_object();
+
+    for (key, value) in LOCAL_LIST.lock().unwrap().iter() {
+        local_list[key.as_str()] = value.clone();
+    }
+
+    let mut reservations = JsonValue::new_object();
+
+    for (key, value) in RESERVATIONS.lock().unwrap().iter() {
+        reservations[key.to_string().as_str()] = value.to_json();
+    }
+
+    let mut charging_profiles = JsonValue::new_object();
+
+    for (key, value) in CHARGING_PROFILES.lock().unwrap().iter() {
+        charging_profiles[key.to_string().as_str()] = object!{
+            "evseId" => value.evse_id,
+            "source" => value.source.as_str(),
+            "chargingProfile" => value.to_json(),
+        };
+    }
+
+    let mut external_limits = JsonValue::new_array();
+
+    for value in EXTERNAL_LIMITS.lock().unwrap().values() {
+        external_limits.push(value.to_json()).unwrap();
+    }
+
+    let mut queue = JsonValue::new_array();
+
+    for msg in QUEUE.lock().unwrap().iter() {
+        queue.push(msg.as_str()).unwrap();
+    }
+
+    let last_sent_message: JsonValue = match LAST_SENT_MESSAGE.lock().unwrap().as_ref() {
+        Some(sent_message) => sent_message.id.as_str().into(),
+        None => JsonValue::Null,
+    };
+
+    object!{
+        "operational" => *STATION_OPERATIONAL.lock().unwrap(),
+        "evses" => evses,
+        "messages" => messages,
+        "transactions" => transactions,
+        "authCache" => auth_cache,
+        "localListVersion" => *LOCAL_LIST_VERSION.lock().unwrap(),
+        "localList" => local_list,
+        "reservations" => reservations,
+        "chargingProfiles" => charging_profiles,
+        "externalLimits" => external_limits,
+        "queue" => queue,
+        "lastSentMessage" => last_sent_message,
+    }
+}
+
+/// Saves the state which has to survive a restart, along with all changes which were not saved yet.
+///
+/// Must not be called while holding a lock of the state.
+fn persist() {
+    *UNSAVED_SINCE.lock().unwrap() = None;
+    persistence::save(snapshot);
+}
+
+/// Marks the state as changed, so that it's saved with the next change or by `persist_changes`.
+fn persist_later() {
+    UNSAVED_SINCE.lock().unwrap().get_or_insert(Utc::now().timestamp() as u64);
+}
+
+/// Saves changes of the state which were not saved for `PERSIST_INTERVAL`.
+pub fn persist_changes() {
+    let unsaved_since = *UNSAVED_SINCE.lock().unwrap();
+
+    if unsaved_since.is_some_and(|since| Utc::now().timestamp() as u64 >= since + PERSIST_INTERVAL) {
+        persist();
+    }
+}
+
+/// Maps a saved connector status to a known one.
+fn connector_status(value: &str) -> &'static str {
+    match value {
+        "Available" => "Available",
+        "Occupied" => "Occupied",
+        "Reserved" => "Reserved",
+        "Unavailable" => "Unavailable",
+        "Faulted" => "Faulted",
+        _ => "Inoperative",
+    }
+}
+
+/// Restores the state saved before a restart: pending messages, open transactions and connector states.
+///
+/// Has to be called after EVSEs are initialized.
+pub fn restore() {
+    let state = match persistence::load() {
+        Some(res) => res,
+        None => return,
+    };
+
+    *STATION_OPERATIONAL.lock().unwrap() = state["operational"].as_bool().unwrap_or(true);
+
+    {
+        let mut evses = EVSES.lock().unwrap();
+
+        let layout_matches = state["evses"].len() == evses.len()
+            && evses.iter().enumerate().all(|(i, evse)| state["evses"][i]["connectors"].len() == evse.connectors.len());
+
+        if layout_matches {
+            for (evse_index, evse) in evses.iter_mut().enumerate() {
+                evse.operational = state["evses"][evse_index]["operational"].as_bool().unwrap_or(true);
+                evse.meter.energy = state["evses"][evse_index]["energy"].as_f64().unwrap_or(0.0);
+
+                if state["evses"][evse_index]["ev"].is_object() {
+                    let mut ev = Ev::default();
+
+                    if ev.update_from_json(&state["evses"][evse_index]["ev"]).is_ok() {
+                        evse.ev = Some(ev);
+                    }
+                }
+
+                for (connector_index, connector) in evse.connectors.iter_mut().enumerate() {
+                    let saved_connector = &state["evses"][evse_index]["connectors"][connector_index];
+
+                    connector.status = connector_status(&saved_connector["status"].to_string());
+                    connector.operational = saved_connector["operational"].as_bool().unwrap_or(true);
+                    connector.locked = saved_connector["locked"].as_bool().unwrap_or(false);
+                }
+            }
+        } else {
+            println!("Saved EVSE layout doesn't match the configured one, connector states are not restored.");
+        }
+    }
+
+    for (key, value) in state["messages"].entries() {
+        MESSAGES.lock().unwrap().insert(key.to_string(), value.to_string());
+    }
+
+    for (key, value) in state["transactions"].entries() {
+        match Transaction::from_json(value) {
+            Some(transaction) => { TRANSACTIONS.lock().unwrap().insert(key.to_string(), transaction); },
+            None => println!("Saved transaction {} can't be read, it's not restored.", key),
+        }
+    }
+
+    for (key, value) in state["authCache"].entries() {
+        if let Some(entry) = CacheEntry::from_json(value) {
+            AUTH_CACHE.lock().unwrap().insert(key.to_string(), entry);
+        }
+    }
+
+    *LOCAL_LIST_VERSION.lock().unwrap() = state["localListVersion"].as_u64().unwrap_or(0);
+
+    for (key, value) in state["localList"].entries() {
+        LOCAL_LIST.lock().unwrap().insert(key.to_string(), value.clone());
+    }
+
+    for (key, value) in state["reservations"].entries() {
+        match Reservation::from_json(value) {
+            Some(reservation) => { RESERVATIONS.lock().unwrap().insert(reservation.id, reservation); },
+            None => println!("Saved reservation {} can't be read, it's not restored.", key),
+        }
+    }
+
+    for (key, value) in state["chargingProfiles"].entries() {
+        match ChargingProfile::from_json(value) {
+            Some(profile) => { CHARGING_PROFILES.lock().unwrap().insert(profile.id, profile); },
+            None => println!("Saved charging profile {} can't be read, it's not restored.", key),
+        }
+    }
+
+    for value in state["externalLimits"].members() {
+        match ExternalLimit::from_json(value) {
+            Some(external_limit) => { EXTERNAL_LIMITS.lock().unwrap().insert((external_limit.evse_id, external_limit.source.to_owned()), external_limit); },
+            None => println!("Saved external limit {} can't be read, it's not restored.", value),
+        }
+    }
+
+    for msg in state["queue"].members() {
+        let msg = msg.to_string();
+
+        // A new BootNotification is sent on startup anyway.
+        match json::parse(&msg) {
+            Ok(parsed_msg) if parsed_msg[2] == "BootNotification" => {
+                MESSAGES.lock().unwrap().remove(&parsed_msg[1].to_string());
+            },
+            _ => QUEUE.lock().unwrap().push_back(msg),
+        }
+    }
+
+    // A transaction-related message which was sent but not answered before the restart has to be sent again.
+    if let Some(id) = state["lastSentMessage"].as_str() {
+        let msg = get_message(id);
+        let is_transaction_event = match json::parse(&msg) {
+            Ok(parsed_msg) => parsed_msg[2] == "TransactionEvent",
+            Err(_) => false,
+        };
+
+        if is_transaction_event {
+            QUEUE.lock().unwrap().push_front(msg);
+        } else {
+            MESSAGES.lock().unwrap().remove(id);
+        }
+    }
+
+    println!("Restored {} transaction(s) and {} queued message(s).", TRANSACTIONS.lock().unwrap().len(), QUEUE.lock().unwrap().len());
 }
 
 pub fn set_message(key: String, value: String) {
     MESSAGES.lock().unwrap().insert(key, value);
+    persist_later();
+}
+
+pub fn delete_message(key: &str) {
+    MESSAGES.lock().unwrap().remove(key);
+    persist_later();
 }
 
 pub fn get_message(key: &str) -> String {
@@ -41,56 +375,417 @@ pub fn get_message(key: &str) -> String {
     }
 }
 
-pub fn set_transaction(key: String, value: String) {
-    TRANSACTIONS.lock().unwrap().insert(key, value);
+/// Saves a transaction. The state is saved right away when the transaction appears or starts.
+pub fn set_transaction(value: Transaction) {
+    let previous_value = TRANSACTIONS.lock().unwrap().insert(value.id.to_owned(), value.to_owned());
+
+    if previous_value.is_none_or(|previous_value| previous_value.started != value.started) {
+        persist();
+    } else {
+        persist_later();
+    }
 }
 
-pub fn get_transaction(key: &str) -> String {
-    match TRANSACTIONS.lock().unwrap().get(key) {
-        Some(value) => value.to_string(),
-        None => String::from(""),
+/// Changes a transaction, e.g. time spent charging on every tick.
+pub fn update_transaction<F: FnOnce(&mut Transaction)>(key: &str, update: F) {
+    if let Some(transaction) = TRANSACTIONS.lock().unwrap().get_mut(key) {
+        update(transaction);
     }
+    persist_later();
+}
+
+pub fn get_transaction(key: &str) -> Option<Transaction> {
+    TRANSACTIONS.lock().unwrap().get(key).cloned()
+}
+
+/// Returns the transaction of an EVSE.
+pub fn get_evse_transaction(evse_id: usize) -> Option<Transaction> {
+    TRANSACTIONS.lock().unwrap().values().find(|transaction| transaction.evse_id == evse_id).cloned()
+}
+
+pub fn get_transactions() -> Vec<Transaction> {
+    TRANSACTIONS.lock().unwrap().values().cloned().collect()
 }
 
 pub fn delete_transaction(key: &str) {
     TRANSACTIONS.lock().unwrap().remove(key);
+    persist();
+}
+
+/// Initialize EVSEs from the station layout: connector types of each EVSE.
+pub fn init_evses(layout: &[Vec<String>]) {
+    let evses: Vec<Evse> = layout.iter().map(|connector_types| Evse {
+        connectors: connector_types.iter().map(|connector_type| Connector {
+            connector_type: connector_type.to_string(),
+            status: "Inoperative",
+            operational: true,
+            locked: false,
+            lock_failure: false,
+        }).collect(),
+        operational: true,
+        meter: Meter::default(),
+        ev: None,
+    }).collect();
+
+    *EVSES.lock().unwrap() = evses;
+}
+
+pub fn get_evses() -> Vec<Evse> {
+    EVSES.lock().unwrap().clone()
+}
+
+pub fn get_evse(evse_index: usize) -> Option<Evse> {
+    EVSES.lock().unwrap().get(evse_index).cloned()
 }
 
 pub fn set_connector_status(evse_index: usize, connector_index: usize, value: &'static str) {
-    EVSES.lock().unwrap()[evse_index][connector_index].status = value;
+    if let Some(connector) = EVSES.lock().unwrap().get_mut(evse_index).and_then(|evse| evse.connectors.get_mut(connector_index)) {
+        connector.status = value;
+    }
+    persist_later();
+}
+
+pub fn set_connector_operational_status(evse_index: usize, connector_index: usize, value: bool) {
+    if let Some(connector) = EVSES.lock().unwrap().get_mut(evse_index).and_then(|evse| evse.connectors.get_mut(connector_index)) {
+        connector.operational = value;
+    }
+    persist_later();
 }
-// NOTE Unused.
-// pub fn set_connector_operational_status(evse_index: usize, connector_index: usize, value: bool) {
-//     EVSES.lock().unwrap()[evse_index][connector_index].operational = value;
-// }
 
-pub fn get_connector(evse_index: usize, connector_index: usize) -> Connector {
-    EVSES.lock().unwrap()[evse_index][connector_index].clone()
+pub fn set_connector_locked(evse_index: usize, connector_index: usize, value: bool) {
+    if let Some(connector) = EVSES.lock().unwrap().get_mut(evse_index).and_then(|evse| evse.connectors.get_mut(connector_index)) {
+        connector.locked = value;
+    }
+    persist_later();
+}
+
+pub fn set_connector_lock_failure(evse_index: usize, connector_index: usize, value: bool) {
+    if let Some(connector) = EVSES.lock().unwrap().get_mut(evse_index).and_then(|evse| evse.connectors.get_mut(connector_index)) {
+        connector.lock_failure = value;
+    }
+}
+
+pub fn set_evse_operational_status(evse_index: usize, value: bool) {
+    if let Some(evse) = EVSES.lock().unwrap().get_mut(evse_index) {
+        evse.operational = value;
+    }
+    persist_later();
+}
+
+pub fn set_station_operational_status(value: bool) {
+    *STATION_OPERATIONAL.lock().unwrap() = value;
+    persist_later();
+}
+
+/// Checks whether a connector is operative: neither the connector, its EVSE nor the station is set inoperative.
+pub fn is_connector_operational(evse_index: usize, connector_index: usize) -> bool {
+    let station_operational = *STATION_OPERATIONAL.lock().unwrap();
+
+    station_operational && EVSES.lock().unwrap().get(evse_index)
+        .is_some_and(|evse| evse.operational && evse.connectors.get(connector_index).is_some_and(|connector| connector.operational))
+}
+
+/// Changes the meter of an EVSE.
+pub fn update_meter<F: FnOnce(&mut Meter)>(evse_index: usize, update: F) {
+    if let Some(evse) = EVSES.lock().unwrap().get_mut(evse_index) {
+        update(&mut evse.meter);
+    }
+    persist_later();
+}
+
+/// Connects an EV to an EVSE, or disconnects it.
+pub fn set_ev(evse_index: usize, value: Option<Ev>) {
+    if let Some(evse) = EVSES.lock().unwrap().get_mut(evse_index) {
+        evse.ev = value;
+    }
+    persist_later();
+}
+
+/// Changes the EV connected to an EVSE.
+pub fn update_ev<F: FnOnce(&mut Ev)>(evse_index: usize, update: F) {
+    if let Some(ev) = EVSES.lock().unwrap().get_mut(evse_index).and_then(|evse| evse.ev.as_mut()) {
+        update(ev);
+    }
+    persist_later();
+}
+
+pub fn get_connector(evse_index: usize, connector_index: usize) -> Option<Connector> {
+    EVSES.lock().unwrap().get(evse_index).and_then(|evse| evse.connectors.get(connector_index)).cloned()
+}
+
+/// Stores an entry of the authorization cache, replacing the entry of the same idToken.
+pub fn set_cache_entry(key: &str, value: CacheEntry) {
+    AUTH_CACHE.lock().unwrap().insert(key.to_string(), value);
+    persist_later();
+}
+
+pub fn get_cache_entry(key: &str) -> Option<CacheEntry> {
+    AUTH_CACHE.lock().unwrap().get(key).cloned()
+}
+
+pub fn get_cache_entries() -> Vec<CacheEntry> {
+    AUTH_CACHE.lock().unwrap().values().cloned().collect()
+}
+
+pub fn delete_cache_entry(key: &str) {
+    AUTH_CACHE.lock().unwrap().remove(key);
+    persist_later();
+}
+
+pub fn clear_cache_entries() {
+    AUTH_CACHE.lock().unwrap().clear();
+    persist_later();
+}
+
+pub fn set_local_list(version: u64, value: HashMap<String, JsonValue>) {
+    *LOCAL_LIST_VERSION.lock().unwrap() = version;
+    *LOCAL_LIST.lock().unwrap() = value;
+    persist_later();
+}
+
+pub fn get_local_list() -> HashMap<String, JsonValue> {
+    LOCAL_LIST.lock().unwrap().clone()
+}
+
+pub fn get_local_list_version() -> u64 {
+    *LOCAL_LIST_VERSION.lock().unwrap()
+}
+
+pub fn get_local_list_entry(key: &str) -> Option<JsonValue> {
+    LOCAL_LIST.lock().unwrap().get(key).cloned()
+}
+
+pub fn set_reservation(value: Reservation) {
+    RESERVATIONS.lock().unwrap().insert(value.id, value);
+    persist_later();
+}
+
+pub fn get_reservation(id: u64) -> Option<Reservation> {
+    RESERVATIONS.lock().unwrap().get(&id).cloned()
+}
+
+pub fn get_evse_reservation(evse_id: usize) -> Option<Reservation> {
+    RESERVATIONS.lock().unwrap().values().find(|reservation| reservation.evse_id == evse_id).cloned()
+}
+
+pub fn get_reservations() -> Vec<Reservation> {
+    RESERVATIONS.lock().unwrap().values().cloned().collect()
+}
+
+pub fn delete_reservation(id: u64) {
+    RESERVATIONS.lock().unwrap().remove(&id);
+    persist_later();
+}
+
+pub fn set_charging_profile(value: ChargingProfile) {
+    CHARGING_PROFILES.lock().unwrap().insert(value.id, value);
+    persist_later();
+}
+
+pub fn get_charging_profiles() -> Vec<ChargingProfile> {
+    CHARGING_PROFILES.lock().unwrap().values().cloned().collect()
+}
+
+pub fn delete_charging_profile(id: u64) {
+    CHARGING_PROFILES.lock().unwrap().remove(&id);
+    persist_later();
+}
+
+/// Sets an external limit, replacing the limit of the same source for the EVSE.
+pub fn set_external_limit(value: ExternalLimit) {
+    EXTERNAL_LIMITS.lock().unwrap().insert((value.evse_id, value.source.to_owned()), value);
+    persist_later();
+}
+
+pub fn get_external_limits() -> Vec<ExternalLimit> {
+    EXTERNAL_LIMITS.lock().unwrap().values().cloned().collect()
+}
+
+pub fn delete_external_limit(evse_id: usize, source: &str) {
+    EXTERNAL_LIMITS.lock().unwrap().remove(&(evse_id, source.to_string()));
+    persist_later();
+}
+
+/// Saves a CALL message, so that a response to it can be handled, and adds it to the queue.
+pub fn queue_message(msg_id: &str, msg: String) {
+    set_message(msg_id.to_string(), msg.to_owned());
+    queue_add(msg);
 }
 
 pub fn queue_size() -> usize {
-    QUEUE.lock().unwrap().size()
+    QUEUE.lock().unwrap().len()
 }
 
 pub fn queue_add(s: String) {
-    match QUEUE.lock().unwrap().add(s) {
-        Err(e) => println!("{:?}", e),
-        _ => (),
+    QUEUE.lock().unwrap().push_back(s);
+    persist();
+}
+
+/// Adds a message to the front of the queue, so that it's sent before the others.
+pub fn queue_add_front(s: String) {
+    QUEUE.lock().unwrap().push_front(s);
+    persist();
+}
+
+/// Removes the first queued message with the given action.
+pub fn queue_remove_action(action: &str) -> String {
+    let s = {
+        let mut queue = QUEUE.lock().unwrap();
+
+        let position = queue.iter().position(|msg| match json::parse(msg) {
+            Ok(parsed_msg) => parsed_msg[2] == action,
+            Err(_) => false,
+        });
+
+        match position {
+            Some(index) => queue.remove(index).unwrap_or_default(),
+            None => return String::new(),
+        }
     };
+    persist();
+    s
 }
 
 pub fn queue_pop() -> String {
-    match QUEUE.lock().unwrap().remove() {
-        Ok(res) => res,
-        Err(_) => String::from(""),
-    }
+    let s = QUEUE.lock().unwrap().pop_front().unwrap_or_default();
+    persist();
+    s
 }
 
-pub fn set_last_sent_message(id: String, timestamp: u64) {
-    LAST_SENT_MESSAGE.lock().unwrap().id = Some(id);
-    LAST_SENT_MESSAGE.lock().unwrap().timestamp = Some(timestamp);
+pub fn set_last_sent_message(value: SentMessage) {
+    *LAST_SENT_MESSAGE.lock().unwrap() = Some(value);
+    persist();
 }
 
-pub fn get_last_sent_message() -> SentMessage {
+pub fn get_last_sent_message() -> Option<SentMessage> {
     LAST_SENT_MESSAGE.lock().unwrap().clone()
 }
+
+pub fn clear_last_sent_message() {
+    *LAST_SENT_MESSAGE.lock().unwrap() = None;
+    persist_later();
+}
+
+pub fn set_registration_status(value: &'static str) {
+    *REGISTRATION_STATUS.lock().unwrap() = value;
+}
+
+pub fn get_registration_status() -> &'static str {
+    *REGISTRATION_STATUS.lock().unwrap()
+}
+
+pub fn is_boot_accepted() -> bool {
+    get_registration_status() == "Accepted"
+}
+
+pub fn add_connection() {
+    *CONNECTIONS.lock().unwrap() += 1;
+}
+
+pub fn connection_count() -> u64 {
+    *CONNECTIONS.lock().unwrap()
+}
+
+pub fn set_boot_reason(value: &'static str) {
+    *BOOT_REASON.lock().unwrap() = value;
+}
+
+pub fn get_boot_reason() -> &'static str {
+    *BOOT_REASON.lock().unwrap()
+}
+
+pub fn schedule_reset(evse_index: Option<usize>) {
+    let mut scheduled_resets = SCHEDULED_RESETS.lock().unwrap();
+
+    if !scheduled_resets.contains(&evse_index) {
+        scheduled_resets.push(evse_index);
+    }
+}
+
+pub fn get_scheduled_resets() -> Vec<Option<usize>> {
+    SCHEDULED_RESETS.lock().unwrap().clone()
+}
+
+pub fn delete_scheduled_reset(evse_index: Option<usize>) {
+    SCHEDULED_RESETS.lock().unwrap().retain(|scheduled_reset| *scheduled_reset != evse_index);
+}
+
+/// Re-initializes the state after a reset, as if the station was powered up again.
+///
+/// EVs are gone and the station has to be registered again. Only transaction-related messages are kept
+/// in the queue, along with data which is saved on restart. Reservations outlast the reset, so connectors
+/// they cover stay reserved.
+pub fn reset() {
+    let station_operational = *STATION_OPERATIONAL.lock().unwrap();
+    let reservations = get_reservations();
+
+    for (evse_index, evse) in EVSES.lock().unwrap().iter_mut().enumerate() {
+        let evse_operational = station_operational && evse.operational;
+        let reservation = reservations.iter().find(|reservation| reservation.evse_id == evse_index + 1);
+
+        evse.ev = None;
+        evse.meter = Meter {
+            energy: evse.meter.energy,
+            ..Meter::default()
+        };
+
+        for connector in evse.connectors.iter_mut() {
+            connector.status = if !evse_operational || !connector.operational {
+                "Unavailable"
+            } else if reservation.is_some_and(|reservation| reservation.covers(connector)) {
+                "Reserved"
+            } else {
+                "Available"
+            };
+            connector.locked = false;
+        }
+    }
+
+    {
+        let mut queue = QUEUE.lock().unwrap();
+        let mut messages = MESSAGES.lock().unwrap();
+
+        queue.retain(|msg| match json::parse(msg) {
+            Ok(parsed_msg) if parsed_msg[2] == "TransactionEvent" => true,
+            Ok(parsed_msg) => {
+                messages.remove(&parsed_msg[1].to_string());
+                false
+            },
+            Err(_) => false,
+        });
+    }
+
+    *REGISTRATION_STATUS.lock().unwrap() = "";
+    SCHEDULED_RESETS.lock().unwrap().clear();
+
+    persist();
+}
+
+/// Returns a new ID for an event reported with NotifyEvent.
+pub fn next_event_id() -> u64 {
+    let mut event_id = EVENT_ID.lock().unwrap();
+
+    *event_id += 1;
+    *event_id
+}
+
+pub fn set_connected(value: bool) {
+    let mut connected = CONNECTED.lock().unwrap();
+
+    if *connected && !value {
+        *DISCONNECTED_AT.lock().unwrap() = Some(Utc::now().timestamp() as u64);
+    }
+
+    *connected = value;
+}
+
+/// Returns how long the connection was lost last time, in seconds, and forgets it, so it's reported only once.
+/// Returns nothing if the connection wasn't lost since then.
+pub fn take_offline_time() -> Option<u64> {
+    DISCONNECTED_AT.lock().unwrap().take().map(|disconnected_at| (Utc::now().timestamp() as u64).saturating_sub(disconnected_at))
+}
+
+/// Checks whether the station is connected to CSMS and accepted by it.
+pub fn is_online() -> bool {
+    *CONNECTED.lock().unwrap() && is_boot_accepted()
+}
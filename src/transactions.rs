@@ -0,0 +1,529 @@
+use std::sync::Mutex;
+
+use chrono::prelude::*;
+use json::JsonValue;
+use uuid::Uuid;
+
+use crate::components;
+use crate::connectors;
+use crate::ev;
+use crate::meter;
+use crate::requests;
+use crate::reservations;
+use crate::smart_charging;
+use crate::storage;
+
+// Transaction struct. Exists from the first event at an EVSE (EV plugged in or driver authorized),
+// is reported to CSMS once a start point is reached and ends once a stop point is left.
+#[derive(Clone, Debug)]
+pub struct Transaction {
+    pub id: String,
+    pub evse_id: usize,
+    // Connector the EV is plugged in, once known.
+    pub connector_id: Option<usize>,
+    // Whether Started event was sent.
+    pub started: bool,
+    // Sequence number of the next TransactionEvent.
+    pub seq_no: u64,
+    pub ev_connected: bool,
+    pub authorized: bool,
+    // When the driver was authorized, in milliseconds.
+    pub authorized_at: u64,
+    // When Started event was sent, in milliseconds.
+    pub started_at: u64,
+    pub id_token: Option<JsonValue>,
+    pub remote_start_id: Option<u64>,
+    // Reservation used by the driver.
+    pub reservation_id: Option<u64>,
+    pub charging_state: String,
+    // Time spent in Charging state, in seconds.
+    pub time_spent_charging: f64,
+    pub stopped_reason: Option<String>,
+    // Whether EVSE and idToken were reported to CSMS.
+    pub evse_sent: bool,
+    pub id_token_sent: bool,
+    // Values sampled every TxEndedInterval, reported with Ended event.
+    pub meter_values: JsonValue,
+}
+
+impl Transaction {
+    fn new(evse_id: usize) -> Transaction {
+        Transaction {
+            id: Uuid::new_v4().to_string(),
+            evse_id,
+            connector_id: None,
+            started: false,
+            seq_no: 0,
+            ev_connected: false,
+            authorized: false,
+            authorized_at: 0,
+            started_at: 0,
+            id_token: None,
+            remote_start_id: None,
+            reservation_id: None,
+            charging_state: "Idle".to_string(),
+            time_spent_charging: 0.0,
+            stopped_reason: None,
+            evse_sent: false,
+            id_token_sent: false,
+            meter_values: JsonValue::new_array(),
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        object!{
+            "id" => self.id.as_str(),
+            "evseId" => self.evse_id,
+            "connectorId" => self.connector_id,
+            "started" => self.started,
+            "seqNo" => self.seq_no,
+            "evConnected" => self.ev_connected,
+            "authorized" => self.authorized,
+            "authorizedAt" => self.authorized_at,
+            "startedAt" => self.started_at,
+            "idToken" => self.id_token.clone(),
+            "remoteStartId" => self.remote_start_id,
+            "reservationId" => self.reservation_id,
+            "chargingState" => self.charging_state.as_str(),
+            "timeSpentCharging" => self.time_spent_charging,
+            "stoppedReason" => self.stopped_reason.clone(),
+            "evseSent" => self.evse_sent,
+            "idTokenSent" => self.id_token_sent,
+            "meterValues" => self.meter_values.clone(),
+        }
+    }
+
+    pub fn from_json(data: &JsonValue) -> Option<Transaction> {
+        Some(Transaction {
+            id: data["id"].as_str()?.to_string(),
+            evse_id: data["evseId"].as_usize()?,
+            connector_id: data["connectorId"].as_usize(),
+            started: data["started"].as_bool()?,
+            seq_no: data["seqNo"].as_u64()?,
+            ev_connected: data["evConnected"].as_bool()?,
+            authorized: data["authorized"].as_bool()?,
+            authorized_at: data["authorizedAt"].as_u64().unwrap_or(0),
+            started_at: data["startedAt"].as_u64().unwrap_or(0),
+            id_token: Some(data["idToken"].clone()).filter(|id_token| id_token.is_object()),
+            remote_start_id: data["remoteStartId"].as_u64(),
+            reservation_id: data["reservationId"].as_u64(),
+            charging_state: data["chargingState"].as_str()?.to_string(),
+            time_spent_charging: data["timeSpentCharging"].as_f64().unwrap_or(0.0),
+            stopped_reason: data["stoppedReason"].as_str().map(String::from),
+            evse_sent: data["evseSent"].as_bool().unwrap_or(false),
+            id_token_sent: data["idTokenSent"].as_bool().unwrap_or(false),
+            meter_values: if data["meterValues"].is_array() { data["meterValues"].clone() } else { JsonValue::new_array() },
+        })
+    }
+}
+
+lazy_static! {
+    // Serializes changes of transactions made by CSMS requests, the meter and the console.
+    static ref LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Reads TxStartPoint or TxStopPoint.
+fn tx_points(variable: &str) -> Vec<String> {
+    components::get_value("TxCtrlr", variable).split(',').filter(|point| !point.is_empty()).map(String::from).collect()
+}
+
+/// Checks whether the condition of a start or stop point holds for a transaction.
+///
+/// Parking bay is considered occupied while an EV is plugged in. Signed meter data is not simulated.
+fn point_reached(transaction: &Transaction, point: &str) -> bool {
+    match point {
+        "ParkingBayOccupancy" | "EVConnected" => transaction.ev_connected,
+        "Authorized" => transaction.authorized,
+        "PowerPathClosed" => is_ready(transaction),
+        "EnergyTransfer" => transaction.charging_state == "Charging",
+        _ => false,
+    }
+}
+
+/// Checks whether the EV of an authorized driver is plugged in and the cable is locked.
+fn is_ready(transaction: &Transaction) -> bool {
+    let locked = transaction.connector_id
+        .and_then(|connector_id| storage::get_connector(transaction.evse_id - 1, connector_id - 1))
+        .is_some_and(|connector| connector.locked);
+
+    transaction.ev_connected && transaction.authorized && locked
+}
+
+/// Checks whether energy may be transferred to the EV at an EVSE.
+pub fn is_power_path_closed(evse_index: usize) -> bool {
+    storage::get_evse_transaction(evse_index + 1).is_some_and(|transaction| is_ready(&transaction))
+}
+
+/// Determines the charging state of a transaction.
+fn charging_state(evse_index: usize, transaction: &Transaction) -> &'static str {
+    if !transaction.ev_connected {
+        return "Idle";
+    }
+
+    if !is_ready(transaction) {
+        return "EVConnected";
+    }
+
+    // Load balancing may leave the EVSE less power than the EV would draw, or none.
+    let power_offered = storage::get_evse(evse_index).map_or(0.0, |evse| evse.meter.power_offered);
+
+    if meter::power_limit(evse_index).min(power_offered) > 0.0 {
+        return "Charging";
+    }
+
+    let ev_full = storage::get_evse(evse_index).and_then(|evse| evse.ev).is_some_and(|ev| ev.is_full());
+
+    if ev_full {
+        "SuspendedEV"
+    } else {
+        "SuspendedEVSE"
+    }
+}
+
+/// Removes a transaction along with its charging profiles.
+fn delete(transaction: &Transaction) {
+    storage::delete_transaction(&transaction.id);
+
+    smart_charging::remove_transaction_profiles(&transaction.id);
+}
+
+/// Sends TransactionEvent with the current state of a transaction.
+fn queue_event(transaction: &mut Transaction, event_type: &str, trigger_reason: &str, meter_value: JsonValue) {
+    let msg_id: &str = &Uuid::new_v4().to_string();
+    let msg = requests::transaction_event(msg_id, transaction, event_type, trigger_reason, !storage::is_online(), meter_value);
+
+    storage::queue_message(msg_id, msg);
+
+    transaction.seq_no += 1;
+    transaction.evse_sent = transaction.connector_id.is_some();
+    transaction.id_token_sent = transaction.id_token.is_some();
+}
+
+/// Disconnects the EV from an EVSE and releases its connector.
+fn disconnect_ev(evse_index: usize, connector_id: Option<usize>) {
+    storage::set_ev(evse_index, None);
+
+    if let Some(connector_index) = connector_id.map(|connector_id| connector_id - 1) {
+        storage::set_connector_locked(evse_index, connector_index, false);
+
+        connectors::queue_status_notification(evse_index, connector_index, connectors::idle_status(evse_index, connector_index));
+    }
+}
+
+/// Sends Ended event and removes a transaction.
+///
+/// The driver takes the EV away once the transaction ends.
+fn end(evse_index: usize, mut transaction: Transaction, trigger_reason: &str, stopped_reason: &str) {
+    transaction.stopped_reason = Some(stopped_reason.to_string());
+
+    let mut meter_value = transaction.meter_values.clone();
+
+    for sampled_meter_value in meter::sample_transaction(evse_index, "TxEndedMeasurands", "Transaction.End").members() {
+        meter_value.push(sampled_meter_value.clone()).unwrap();
+    }
+
+    queue_event(&mut transaction, "Ended", trigger_reason, meter_value);
+
+    delete(&transaction);
+
+    println!("Transaction {} on EVSE {} ended ({}).", transaction.id, transaction.evse_id, stopped_reason);
+
+    if transaction.ev_connected {
+        disconnect_ev(evse_index, transaction.connector_id);
+    }
+}
+
+/// Applies a change to the transaction of an EVSE, creating the transaction if there is none.
+///
+/// Sends Started once any of TxStartPoint is reached, Ended once any of TxStopPoint is left,
+/// otherwise reports the change with Updated event.
+fn change<F: FnOnce(&mut Transaction)>(evse_index: usize, trigger_reason: &str, stopped_reason: &str, update: F) {
+    let evse_id = evse_index + 1;
+    let mut transaction = storage::get_evse_transaction(evse_id).unwrap_or_else(|| Transaction::new(evse_id));
+    let previous_transaction = transaction.clone();
+
+    update(&mut transaction);
+
+    // The cable is locked before the power path is closed. The transaction can't start if locking fails.
+    if let Some(connector_id) = transaction.connector_id.filter(|_| transaction.ev_connected && transaction.authorized) {
+        connectors::lock(evse_index, connector_id - 1);
+    }
+
+    transaction.charging_state = charging_state(evse_index, &transaction).to_string();
+
+    if !transaction.started {
+        if tx_points("TxStartPoint").iter().any(|point| point_reached(&transaction, point)) {
+            transaction.started = true;
+            transaction.started_at = Utc::now().timestamp_millis() as u64;
+
+            println!("Transaction {} on EVSE {} started ({}).", transaction.id, evse_id, trigger_reason);
+
+            let meter_value = meter::sample_transaction(evse_index, "TxStartedMeasurands", "Transaction.Begin");
+
+            queue_event(&mut transaction, "Started", trigger_reason, meter_value);
+        }
+
+        if transaction.started || transaction.ev_connected || transaction.authorized {
+            storage::set_transaction(transaction);
+        } else {
+            delete(&transaction);
+        }
+
+        return;
+    }
+
+    // Unless TxCtrlr.StopTxOnEVSideDisconnect is set, unplugging the EV suspends the transaction until the EV
+    // is plugged in again within TxCtrlr.EVConnectionTimeOut.
+    let ev_disconnected = previous_transaction.ev_connected && !transaction.ev_connected;
+    let suspended = ev_disconnected && transaction.authorized && !components::get_bool("TxCtrlr", "StopTxOnEVSideDisconnect");
+
+    if suspended {
+        transaction.authorized_at = Utc::now().timestamp_millis() as u64;
+
+        println!("Transaction {} on EVSE {} is suspended until the EV is plugged in again.", transaction.id, evse_id);
+    }
+
+    let stop_point_left = tx_points("TxStopPoint").iter()
+        .any(|point| point_reached(&previous_transaction, point) && !point_reached(&transaction, point));
+
+    if (stop_point_left && !suspended) || (!transaction.ev_connected && !transaction.authorized) {
+        end(evse_index, transaction, trigger_reason, stopped_reason);
+
+        return;
+    }
+
+    queue_event(&mut transaction, "Updated", trigger_reason, JsonValue::new_array());
+
+    storage::set_transaction(transaction);
+}
+
+/// Plugs the EV in a connector.
+pub fn plug_in(evse_index: usize, connector_index: usize) -> Result<(), String> {
+    let _lock = LOCK.lock().unwrap();
+
+    let evse = match storage::get_evse(evse_index) {
+        Some(res) => res,
+        None => return Err(format!("EVSE {} doesn't exist", evse_index + 1)),
+    };
+
+    if evse.ev.is_some() {
+        return Err(format!("EV is already plugged in EVSE {}", evse_index + 1));
+    }
+
+    match evse.connectors.get(connector_index) {
+        Some(_) if connectors::is_connector_available(evse_index, connector_index) => (),
+        Some(connector) => return Err(format!("Connector {} of EVSE {} is {}", connector_index + 1, evse_index + 1, connector.status)),
+        None => return Err(format!("Connector {} of EVSE {} doesn't exist", connector_index + 1, evse_index + 1)),
+    };
+
+    storage::set_ev(evse_index, Some(ev::new_ev()));
+
+    connectors::queue_status_notification(evse_index, connector_index, "Occupied");
+
+    // Without authorization (AuthCtrlr.Enabled unset) the EV is allowed to charge once it's plugged in, unless
+    // the EVSE is reserved. Then the driver it's reserved for has to present their token, which uses the reservation.
+    let authorization_enabled = components::get_bool("AuthCtrlr", "Enabled");
+    let reserved = storage::get_evse_reservation(evse_index + 1).is_some();
+
+    if !authorization_enabled && reserved {
+        println!("EVSE {} is reserved, the EV waits for the driver to present their IdToken.", evse_index + 1);
+    }
+
+    change(evse_index, "CablePluggedIn", "EVDisconnected", |transaction| {
+        transaction.ev_connected = true;
+        transaction.connector_id = Some(connector_index + 1);
+
+        if !authorization_enabled && !reserved && !transaction.authorized {
+            transaction.authorized = true;
+            transaction.authorized_at = Utc::now().timestamp_millis() as u64;
+            transaction.id_token = Some(object!{ "idToken" => "", "type" => "NoAuthorization" });
+        }
+    });
+
+    Ok(())
+}
+
+/// Unplugs the EV from an EVSE.
+pub fn unplug(evse_index: usize) -> Result<(), String> {
+    let _lock = LOCK.lock().unwrap();
+
+    let transaction = match storage::get_evse_transaction(evse_index + 1) {
+        Some(res) if res.ev_connected => res,
+        _ => return Err(format!("No EV is plugged in EVSE {}", evse_index + 1)),
+    };
+
+    disconnect_ev(evse_index, transaction.connector_id);
+
+    change(evse_index, "EVCommunicationLost", "EVDisconnected", |transaction| transaction.ev_connected = false);
+
+    Ok(())
+}
+
+/// Authorizes the driver at an EVSE. The driver uses the reservation of the EVSE, if there is one.
+pub fn authorize(evse_index: usize, id_token: JsonValue, remote_start_id: Option<u64>, trigger_reason: &str) {
+    let _lock = LOCK.lock().unwrap();
+
+    let reservation_id = reservations::use_reservation(evse_index);
+
+    change(evse_index, trigger_reason, "Other", |transaction| {
+        transaction.authorized = true;
+        transaction.authorized_at = Utc::now().timestamp_millis() as u64;
+        transaction.id_token = Some(id_token);
+        transaction.id_token_sent = false;
+
+        if remote_start_id.is_some() {
+            transaction.remote_start_id = remote_start_id;
+        }
+
+        if reservation_id.is_some() {
+            transaction.reservation_id = reservation_id;
+        }
+    });
+}
+
+/// Withdraws authorization of the transaction of an EVSE, which stops energy transfer.
+pub fn deauthorize(evse_index: usize, trigger_reason: &str, stopped_reason: &str) {
+    let _lock = LOCK.lock().unwrap();
+
+    if storage::get_evse_transaction(evse_index + 1).is_none() {
+        return;
+    }
+
+    change(evse_index, trigger_reason, stopped_reason, |transaction| transaction.authorized = false);
+}
+
+/// Stops the transaction of an EVSE regardless of TxStopPoint.
+///
+/// A transaction which isn't started is unknown to CSMS, so it's discarded without TransactionEvent. The driver
+/// loses the authorization and the EV is taken away, as if the transaction ended.
+pub fn stop(evse_index: usize, trigger_reason: &str, stopped_reason: &str) {
+    let _lock = LOCK.lock().unwrap();
+
+    let transaction = match storage::get_evse_transaction(evse_index + 1) {
+        Some(res) => res,
+        None => return,
+    };
+
+    if transaction.started {
+        end(evse_index, transaction, trigger_reason, stopped_reason);
+
+        return;
+    }
+
+    delete(&transaction);
+
+    println!("Transaction {} on EVSE {} was discarded before it started ({}).", transaction.id, transaction.evse_id, stopped_reason);
+
+    if transaction.ev_connected {
+        disconnect_ev(evse_index, transaction.connector_id);
+    }
+}
+
+/// Checks whether an EVSE, or the whole station if no EVSE is given, has no started transactions.
+pub fn is_idle(evse_index: Option<usize>) -> bool {
+    !storage::get_transactions().iter()
+        .any(|transaction| transaction.started && evse_index.is_none_or(|evse_index| transaction.evse_id == evse_index + 1))
+}
+
+/// Updates the transaction of an EVSE after the meter was updated.
+///
+/// Counts time spent charging, reports charging state changes and cancels authorization
+/// when the EV isn't plugged in within TxCtrlr.EVConnectionTimeOut.
+pub fn update(evse_index: usize, elapsed: f64) {
+    let _lock = LOCK.lock().unwrap();
+
+    let mut transaction = match storage::get_evse_transaction(evse_index + 1) {
+        Some(res) => res,
+        None => return,
+    };
+
+    if transaction.authorized && !transaction.ev_connected {
+        let ev_connection_timeout = components::get_integer("TxCtrlr", "EVConnectionTimeOut");
+
+        if Utc::now().timestamp_millis() as u64 >= transaction.authorized_at + ev_connection_timeout * 1000 {
+            println!("EV wasn't plugged in EVSE {} within {} s, authorization is cancelled.", evse_index + 1, ev_connection_timeout);
+
+            change(evse_index, "EVConnectTimeout", "Timeout", |transaction| transaction.authorized = false);
+
+            return;
+        }
+    }
+
+    if transaction.charging_state == "Charging" {
+        transaction.time_spent_charging += elapsed;
+
+        storage::update_transaction(&transaction.id, |transaction| transaction.time_spent_charging += elapsed);
+    }
+
+    let new_charging_state = charging_state(evse_index, &transaction);
+
+    if new_charging_state != transaction.charging_state {
+        println!("EVSE {} charging state is {}.", evse_index + 1, new_charging_state);
+
+        let stopped_reason = if new_charging_state == "SuspendedEV" { "StoppedByEV" } else { "Other" };
+
+        change(evse_index, "ChargingStateChanged", stopped_reason, |_| ());
+    }
+}
+
+/// Sends meter values of the started transaction of an EVSE with Updated event.
+pub fn report_meter_values(evse_index: usize, trigger_reason: &str, meter_value: JsonValue) {
+    let _lock = LOCK.lock().unwrap();
+
+    if let Some(mut transaction) = storage::get_evse_transaction(evse_index + 1).filter(|transaction| transaction.started) {
+        queue_event(&mut transaction, "Updated", trigger_reason, meter_value);
+
+        storage::set_transaction(transaction);
+    }
+}
+
+/// Sends meter values of every started transaction once the station is back online, to report energy
+/// which was delivered while it was offline.
+pub fn report_offline_energy() {
+    for transaction in storage::get_transactions().into_iter().filter(|transaction| transaction.started) {
+        let evse_index = transaction.evse_id - 1;
+
+        // Values are taken at the end of the interruption, which the lost connection was.
+        let meter_value = meter::sample_transaction(evse_index, "TxUpdatedMeasurands", "Interruption.End");
+
+        if !meter_value.is_empty() {
+            report_meter_values(evse_index, "MeterValuePeriodic", meter_value);
+        }
+    }
+}
+
+/// Keeps meter values of the started transaction of an EVSE until it ends.
+pub fn add_meter_values(evse_index: usize, meter_value: JsonValue) {
+    let _lock = LOCK.lock().unwrap();
+
+    if let Some(mut transaction) = storage::get_evse_transaction(evse_index + 1).filter(|transaction| transaction.started) {
+        for sampled_meter_value in meter_value.members() {
+            transaction.meter_values.push(sampled_meter_value.clone()).unwrap();
+        }
+
+        storage::set_transaction(transaction);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stop_discards_transaction_which_did_not_start() {
+        storage::init_evses(&[vec!["cType2".to_string()]]);
+
+        // The EV is plugged in, but the driver isn't authorized yet.
+        storage::set_ev(0, Some(ev::new_ev()));
+        storage::set_connector_status(0, 0, "Occupied");
+        storage::set_transaction(Transaction { connector_id: Some(1), ev_connected: true, ..Transaction::new(1) });
+
+        stop(0, "ResetCommand", "ImmediateReset");
+
+        // The transaction is unknown to CSMS, so it's not reported. The EV is taken away.
+        assert!(storage::get_evse_transaction(1).is_none());
+        assert_eq!(storage::queue_remove_action("TransactionEvent"), "");
+        assert!(storage::get_evse(0).is_some_and(|evse| evse.ev.is_none()));
+        assert_eq!(storage::get_connector(0, 0).map(|connector| connector.status), Some("Available"));
+    }
+}